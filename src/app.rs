@@ -1,22 +1,41 @@
-use super::db::{Database, Item};
-use super::feed::{load_feeds, Feed};
+use super::conf::{Config, SavedView, Sort};
+use super::db::{Database, Download, DownloadState, FeedStatus, Item};
+use super::download::download_path;
+use super::links::{copy_to_clipboard, extract_links};
+use super::feed::{load_feeds, Feed, FeedOptions};
+use super::keys::Keymap;
+use super::query::{self, Expr, Matcher};
+use super::update::Progress;
 use chrono::{Local, TimeZone, Utc};
 use regex::{Regex, RegexBuilder};
+use std::cmp::Reverse;
 use std::collections::HashMap;
-use std::path::{Path, PathBuf};
-use tui::widgets::TableState;
+use std::error::Error;
+use std::path::PathBuf;
+use tui::widgets::{ListState, TableState};
 
 pub enum InputMode {
     Normal,
     Search,
+    Health,
+    Tags,
+    Channels,
+    FullTextSearch,
+    Query,
+    Views,
+    Links,
+    Help,
 }
 
 pub struct Filter {
     pub read: Option<bool>,
     pub starred: Option<bool>,
     pub feeds: Vec<String>,
-    pub keywords: Vec<String>,
+    pub keywords: Vec<Regex>,
+    pub use_keywords: bool,
     pub tags: Vec<String>,
+    pub query: Option<Expr>,
+    pub query_raw: String,
 }
 
 impl Default for Filter {
@@ -26,40 +45,87 @@ impl Default for Filter {
             starred: None,
             feeds: vec![],
             keywords: vec![],
+            use_keywords: true,
             tags: vec![],
+            query: None,
+            query_raw: String::new(),
         }
     }
 }
 
+// Join expressions with `or`
+fn any_of<I>(exprs: I) -> Option<Expr>
+where
+    I: Iterator<Item = Expr>,
+{
+    let mut exprs = exprs;
+    let first = exprs.next()?;
+    Some(exprs.fold(first, Expr::or))
+}
+
 impl Filter {
-    pub fn filter_feed(&self, feed: &Feed) -> bool {
-        (self.feeds.is_empty() || self.feeds.contains(&feed.url))
-            && (self.tags.is_empty() || self.tags.iter().any(|tag| feed.tags.contains(tag)))
-    }
-
-    pub fn filter_item(&self, item: &Item) -> bool {
-        (match self.read {
-            Some(read) => item.read == read,
-            None => true,
-        }) && (match self.starred {
-            Some(starred) => item.starred == starred,
-            None => true,
-        }) && (self.keywords.is_empty()
-            || self.keywords.iter().any(|kw| match &item.title {
-                Some(title) => title.contains(kw),
-                None => false,
-            }))
+    // The toggles and the user's query as a single expression,
+    // or `None` if nothing is filtered out
+    pub fn to_expr(&self) -> Option<Expr> {
+        let mut terms = Vec::new();
+        if let Some(read) = self.read {
+            terms.push(if read { Expr::Read } else { Expr::Read.not() });
+        }
+        if let Some(starred) = self.starred {
+            terms.push(if starred { Expr::Starred } else { Expr::Starred.not() });
+        }
+        terms.extend(any_of(
+            self.feeds.iter().map(|url| Expr::Feed(Matcher::Text(url.to_lowercase()))),
+        ));
+        terms.extend(any_of(
+            self.tags.iter().map(|tag| Expr::Tag(Matcher::Text(tag.to_lowercase()))),
+        ));
+        if self.use_keywords {
+            terms.extend(any_of(self.keywords.iter().map(|kw| {
+                Expr::Title(Matcher::Regex(kw.clone()))
+                    .or(Expr::Description(Matcher::Regex(kw.clone())))
+            })));
+        }
+        terms.extend(self.query.clone());
+
+        let mut terms = terms.into_iter();
+        let first = terms.next()?;
+        Some(terms.fold(first, Expr::and))
+    }
+}
+
+fn search_regex(query: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(&format!(r"({})", query))
+        .case_insensitive(true)
+        .build()
+}
+
+fn sort_items(items: &mut [Item], sort: Sort, feeds: &HashMap<String, Feed>) {
+    match sort {
+        Sort::Newest => items.sort_by_cached_key(|i| match i.published_at {
+            Some(ts) => -ts,
+            None => 0,
+        }),
+        Sort::Oldest => items.sort_by_cached_key(|i| i.published_at.unwrap_or(0)),
+        Sort::Title => items.sort_by_cached_key(|i| i.title.as_deref().unwrap_or("").to_lowercase()),
+        Sort::Feed => items.sort_by_cached_key(|i| match feeds.get(&i.feed) {
+            Some(feed) => (feed.title.to_lowercase(), -i.published_at.unwrap_or(0)),
+            None => (String::new(), -i.published_at.unwrap_or(0)),
+        }),
     }
 }
 
 pub enum Status {
     Idle,
-    Updating,
+    Updating(Progress),
+    // Until the next update, to summarize the last one
+    Updated(Progress),
 }
 
 pub struct App {
     db: Database,
     feeds_path: PathBuf,
+    download_dir: PathBuf,
 
     pub focus_reader: bool,
     pub status: Status,
@@ -67,6 +133,9 @@ pub struct App {
     pub last_updated: i64,
 
     pub filter: Filter,
+    pub sort: Sort,
+    pub views: Vec<(SavedView, Option<Expr>)>,
+    pub view_name: Option<String>,
     pub items: Vec<Item>,
     pub feeds: HashMap<String, Feed>,
     pub table: StatefulTable,
@@ -76,25 +145,73 @@ pub struct App {
     pub search_input: Option<Regex>,
     pub search_query: Option<Regex>,
 
+    pub fts_query: Option<String>,
+    pub fts_snippets: Vec<String>,
+
+    pub query_input: String,
+    pub query_error: Option<String>,
+
     pub reader_scroll: u16,
+    // Show the summary rather than the full content
+    pub show_summary: bool,
+    // Shown in the status bar until the next key press
+    pub message: Option<String>,
     pub marked: Vec<usize>,
+
+    // Each feed's status, with its row in the table
+    pub health: StatefulTable<(FeedStatus, Vec<String>)>,
+    pub selector: Selector,
+    pub keymap: Keymap,
+    pub help: StatefulTable,
 }
 
 impl App {
-    pub fn new<P>(db_path: P, feeds_path: P) -> App
-    where
-        P: AsRef<Path>,
-    {
-        App {
-            db: Database::new(db_path),
-            feeds_path: feeds_path.as_ref().to_path_buf(),
+    pub fn new(config: &Config) -> Result<App, Box<dyn Error>> {
+        let keywords = config
+            .keywords
+            .iter()
+            .map(|kw| RegexBuilder::new(kw).case_insensitive(true).build())
+            .collect::<Result<Vec<Regex>, _>>()?;
+
+        let mut views = Vec::new();
+        for view in &config.views {
+            let expr = if view.query.trim().is_empty() {
+                None
+            } else {
+                let expr = query::parse(&view.query)
+                    .map_err(|err| format!("Invalid query for view \"{}\": {}", view.name, err))?;
+                Some(expr)
+            };
+            if let Some(search) = &view.search {
+                search_regex(search)
+                    .map_err(|err| format!("Invalid search for view \"{}\": {}", view.name, err))?;
+            }
+            views.push((view.clone(), expr));
+        }
+
+        let keymap = Keymap::new(&config.keys).map_err(|err| format!("Invalid key bindings: {}", err))?;
+
+        let db = Database::new(&config.db_path)?;
+        let feeds = load_feeds(&config.feeds_path)?;
+        db.sync_feed_titles(feeds.iter().map(|f| (f.url.as_str(), f.title.as_str())))?;
+
+        Ok(App {
+            db,
+            feeds_path: config.feeds_path.clone(),
+            download_dir: config.download_dir.clone(),
 
             input_mode: InputMode::Normal,
             focus_reader: false,
             status: Status::Idle,
             last_updated: 0,
 
-            filter: Filter::default(),
+            filter: Filter {
+                keywords,
+                ..Filter::default()
+            },
+            sort: Sort::Newest,
+            views,
+            view_name: None,
             items: Vec::new(),
             feeds: HashMap::default(),
             table: StatefulTable::new(),
@@ -104,37 +221,54 @@ impl App {
             search_results: Vec::new(),
             search_input_raw: String::new(),
 
+            fts_query: None,
+            fts_snippets: Vec::new(),
+
+            query_input: String::new(),
+            query_error: None,
+
             reader_scroll: 0,
+            show_summary: false,
+            message: None,
             marked: Vec::new(),
-        }
+
+            health: StatefulTable::new(),
+            selector: Selector::new(),
+            keymap,
+            help: StatefulTable::new(),
+        })
     }
 
     // Load items according to filter
     pub fn _load_items(&mut self) -> Vec<Item> {
-        // Also store feeds for referencing later
-        for feed in load_feeds(&self.feeds_path) {
-            self.feeds.insert(feed.url.clone(), feed);
+        // Also store feeds for referencing later,
+        // keeping the last good ones if the feeds file has become invalid
+        if let Ok(feeds) = load_feeds(&self.feeds_path) {
+            self.feeds = feeds.into_iter().map(|feed| (feed.url.clone(), feed)).collect();
         }
 
-        let feeds = load_feeds(&self.feeds_path).filter(|f| self.filter.filter_feed(f));
-
-        // TODO why do I need both flat map and flatten?
-        let mut items: Vec<Item> = feeds
-            .flat_map(|feed| self.db.get_feed_items(&feed.url).ok())
-            .flatten()
-            .filter(|i| self.filter.filter_item(i))
-            .collect();
+        let expr = self.filter.to_expr();
+        let mut items: Vec<Item> = Vec::new();
+        for feed in self.feeds.values() {
+            if let Ok(feed_items) = self.db.get_feed_items(&feed.url) {
+                items.extend(feed_items.into_iter().filter(|item| match &expr {
+                    Some(expr) => expr.matches(item, feed),
+                    None => true,
+                }));
+            }
+        }
 
-        // Most recent first
-        items.sort_by_cached_key(|i| match i.published_at {
-            Some(ts) => -ts,
-            None => 0,
-        });
+        sort_items(&mut items, self.sort, &self.feeds);
 
         items
     }
 
     pub fn load_new_items(&mut self) {
+        // Search results are a fixed list
+        if self.fts_query.is_some() {
+            return;
+        }
+
         let mut new: Vec<Item> = self
             ._load_items()
             .into_iter()
@@ -142,12 +276,8 @@ impl App {
             .collect();
         self.last_updated = Utc::now().timestamp();
 
-        // Add and sort recent first
         self.items.append(&mut new);
-        self.items.sort_by_cached_key(|i| match i.published_at {
-            Some(ts) => -ts,
-            None => 0,
-        });
+        sort_items(&mut self.items, self.sort, &self.feeds);
 
         self.update_items_table();
     }
@@ -198,11 +328,7 @@ impl App {
     }
 
     pub fn build_query(&self, query: &str) -> Regex {
-        let regex = format!(r"({})", query);
-        RegexBuilder::new(&regex)
-            .case_insensitive(true)
-            .build()
-            .expect("Invalid regex")
+        search_regex(query).expect("Invalid regex")
     }
 
     pub fn execute_search(&mut self, query: &Regex) {
@@ -227,6 +353,160 @@ impl App {
         self.input_mode = InputMode::Normal;
     }
 
+    pub fn start_full_text_search(&mut self) {
+        self.input_mode = InputMode::FullTextSearch;
+    }
+
+    // Replace the item list with the best matches from the whole database
+    pub fn execute_full_text_search(&mut self, query: &str) {
+        let results = self.db.search_items(query, 500).unwrap_or_default();
+        let (items, snippets) = results.into_iter().unzip();
+        self.items = items;
+        self.fts_snippets = snippets;
+        self.fts_query = Some(query.to_string());
+        self.search_results.clear();
+        self.marked.clear();
+        self.table.state.select(if self.items.is_empty() { None } else { Some(0) });
+        self.reset_reader_scroll();
+        self.update_items_table();
+    }
+
+    pub fn clear_full_text_search(&mut self) {
+        self.fts_query = None;
+        self.fts_snippets.clear();
+        self.marked.clear();
+        self.table.state.select(None);
+        self.load_items();
+    }
+
+    pub fn show_views(&mut self) {
+        let options = self.view_options();
+        self.selector.set_options(options, &[]);
+        self.input_mode = InputMode::Views;
+    }
+
+    // Recount unread items once an update is done, only relabeling the
+    // options so the highlighted view stays put
+    pub fn refresh_view_counts(&mut self) {
+        if let InputMode::Views = self.input_mode {
+            let options = self.view_options();
+            for (option, (_, label)) in self.selector.options.iter_mut().zip(options) {
+                option.1 = label;
+            }
+        }
+    }
+
+    // Each view with its unread items counted across the whole database,
+    // which evaluates every view against every item, so it's done sparingly
+    fn view_options(&self) -> Vec<(String, String)> {
+        let mut counts = vec![0; self.views.len() + 1];
+        for feed in self.feeds.values() {
+            for item in self.db.get_feed_items(&feed.url).unwrap_or_default() {
+                if item.read {
+                    continue;
+                }
+                counts[0] += 1;
+                for (i, (_, expr)) in self.views.iter().enumerate() {
+                    let matches = match expr {
+                        Some(expr) => expr.matches(&item, feed),
+                        None => true,
+                    };
+                    if matches {
+                        counts[i + 1] += 1;
+                    }
+                }
+            }
+        }
+
+        let mut options = vec![("".to_string(), format!("All items ({} unread)", counts[0]))];
+        for (i, (view, _)) in self.views.iter().enumerate() {
+            options.push((i.to_string(), format!("{} ({} unread)", view.name, counts[i + 1])));
+        }
+        options
+    }
+
+    // Switch to the highlighted view, replacing the current filter
+    pub fn apply_view(&mut self) {
+        let selected = self
+            .selector
+            .state
+            .selected()
+            .map(|i| self.selector.options[self.selector.matches[i]].0.clone());
+        if let Some(value) = selected {
+            let view = value.parse::<usize>().ok().map(|i| self.views[i].clone());
+            let keywords = self.filter.keywords.drain(..).collect();
+            self.filter = Filter {
+                read: None,
+                keywords,
+                ..Filter::default()
+            };
+            self.search_query = None;
+            self.search_results.clear();
+            match view {
+                Some((view, expr)) => {
+                    self.filter.query = expr;
+                    self.filter.query_raw = view.query.trim().to_string();
+                    self.sort = view.sort;
+                    self.search_query = view.search.as_deref().map(|s| self.build_query(s));
+                    self.view_name = Some(view.name);
+                }
+                None => {
+                    self.filter.read = Filter::default().read;
+                    self.sort = Sort::Newest;
+                    self.view_name = None;
+                }
+            }
+            self.table.state.select(None);
+            self.marked.clear();
+            self.load_items();
+            if let Some(re) = self.search_query.clone() {
+                self.execute_search(&re);
+            }
+        }
+        self.input_mode = InputMode::Normal;
+    }
+
+    pub fn start_query(&mut self) {
+        self.query_input = self.filter.query_raw.clone();
+        self.query_error = None;
+        self.input_mode = InputMode::Query;
+    }
+
+    // Check the query as it's typed so errors show up right away
+    pub fn update_query_input(&mut self) {
+        self.query_error = if self.query_input.trim().is_empty() {
+            None
+        } else {
+            query::parse(&self.query_input).err().map(|err| err.to_string())
+        };
+    }
+
+    // Apply the query unless it has errors. An empty query clears it.
+    pub fn apply_query(&mut self) {
+        let query = if self.query_input.trim().is_empty() {
+            None
+        } else {
+            match query::parse(&self.query_input) {
+                Ok(expr) => Some(expr),
+                Err(err) => {
+                    self.query_error = Some(err.to_string());
+                    return;
+                }
+            }
+        };
+        self.filter.query = query;
+        self.filter.query_raw = self.query_input.trim().to_string();
+        self.input_mode = InputMode::Normal;
+        self.table.state.select(None);
+        self.marked.clear();
+        self.load_items();
+    }
+
+    pub fn cancel_query(&mut self) {
+        self.query_error = None;
+        self.input_mode = InputMode::Normal;
+    }
+
     pub fn scroll_items_up(&mut self) {
         self.table.previous();
         self.mark_selected_read();
@@ -251,6 +531,22 @@ impl App {
         self.reset_reader_scroll();
     }
 
+    pub fn jump_to_first_item(&mut self) {
+        if !self.table.items.is_empty() {
+            self.table.state.select(Some(0));
+            self.mark_selected_read();
+            self.reset_reader_scroll();
+        }
+    }
+
+    pub fn jump_to_last_item(&mut self) {
+        if !self.table.items.is_empty() {
+            self.table.state.select(Some(self.table.items.len() - 1));
+            self.mark_selected_read();
+            self.reset_reader_scroll();
+        }
+    }
+
     pub fn reset_reader_scroll(&mut self) {
         self.reader_scroll = 0;
     }
@@ -269,12 +565,178 @@ impl App {
         self.focus_reader = !self.focus_reader;
     }
 
-    pub fn open_selected(&self) {
-        if let Some(i) = self.table.state.selected() {
-            if let Some(url) = &self.items[i].url {
-                webbrowser::open(&url).unwrap();
+    // The selected item's id and url, if it has a page to fetch the article from
+    // The item's id and url, and its feed's options for the request
+    pub fn start_article_fetch(&mut self) -> Option<(i64, String, FeedOptions)> {
+        let item = &self.items[self.table.state.selected()?];
+        let options = match self.feeds.get(&item.feed) {
+            Some(feed) => feed.options.clone(),
+            None => FeedOptions::default(),
+        };
+        let fetch = (item.id?, item.url.clone()?, options);
+        self.message = Some("Fetching article...".to_string());
+        Some(fetch)
+    }
+
+    pub fn set_article(&mut self, id: i64, result: Result<String, String>) {
+        match result {
+            Ok(article) => {
+                if let Err(err) = self.db.set_item_article(id, &article) {
+                    self.message = Some(format!("Couldn't save article: {}", err));
+                    return;
+                }
+                if let Some(item) = self.items.iter_mut().find(|item| item.id == Some(id)) {
+                    item.article = Some(article);
+                }
+                self.show_summary = false;
+                self.message = None;
             }
+            Err(err) => self.message = Some(format!("Couldn't fetch article: {}", err)),
+        }
+    }
+
+    // Queue the selected item's attachments that aren't downloaded or
+    // downloading already, returning them for the download manager
+    pub fn queue_downloads(&mut self) -> Vec<i64> {
+        let i = match self.table.state.selected() {
+            Some(i) => i,
+            None => return Vec::new(),
+        };
+        let item = &self.items[i];
+        let feed_title = match self.feeds.get(&item.feed) {
+            Some(feed) => feed.title.clone(),
+            None => item.feed.clone(),
         };
+
+        let mut queued = Vec::new();
+        for enclosure in &item.enclosures {
+            let id = match enclosure.id {
+                Some(id) => id,
+                None => continue,
+            };
+            if let Some(download) = &enclosure.download {
+                if download.state != DownloadState::Failed {
+                    continue;
+                }
+            }
+            let path = download_path(&self.download_dir, &feed_title, id, enclosure);
+            self.db.queue_download(id, &path).unwrap();
+            queued.push(id);
+        }
+
+        if let Some(id) = item.id {
+            self.items[i].enclosures = self.db.get_item_enclosures(id).unwrap();
+        }
+        self.message = Some(match queued.len() {
+            0 => "Nothing to download".to_string(),
+            1 => "Queued 1 download".to_string(),
+            n => format!("Queued {} downloads", n),
+        });
+        queued
+    }
+
+    pub fn update_download(&mut self, download: Download) {
+        let enclosure = self
+            .items
+            .iter_mut()
+            .flat_map(|item| item.enclosures.iter_mut())
+            .find(|enclosure| enclosure.id == Some(download.enclosure));
+        if let Some(enclosure) = enclosure {
+            enclosure.download = Some(download);
+        }
+    }
+
+    pub fn toggle_summary(&mut self) {
+        self.show_summary = !self.show_summary;
+        self.reset_reader_scroll();
+    }
+
+    pub fn selected_feed(&self) -> Option<String> {
+        self.table.state.selected().map(|i| self.items[i].feed.clone())
+    }
+
+    // The richest body, unless the summary was asked for
+    pub fn item_body<'a>(&self, item: &'a Item) -> Option<&'a str> {
+        let summary = item.description.as_deref();
+        let content = item.article.as_deref().or(item.content.as_deref());
+        if self.show_summary {
+            summary.or(content)
+        } else {
+            content.or(summary)
+        }
+    }
+
+    // Pick from the selected item's own link, numbered 0,
+    // and the links in its body, numbered as in the reader
+    pub fn show_links(&mut self) {
+        let item = match self.table.state.selected() {
+            Some(i) => &self.items[i],
+            None => return,
+        };
+        let mut options = Vec::new();
+        if let Some(url) = &item.url {
+            options.push((url.clone(), format!("[0] {}", url)));
+        }
+        if let Some(body) = self.item_body(item) {
+            let (_, links) = extract_links(body);
+            for (i, link) in links.into_iter().enumerate() {
+                let label = format!("[{}] {}", i + 1, link);
+                options.push((link, label));
+            }
+        }
+        if options.is_empty() {
+            self.message = Some("No links".to_string());
+            return;
+        }
+        self.selector.set_options(options, &[]);
+        self.input_mode = InputMode::Links;
+    }
+
+    // Typing a link's number highlights it
+    pub fn push_link_number(&mut self, c: char) {
+        self.selector.query.push(c);
+        self.select_link_number();
+    }
+
+    pub fn pop_link_number(&mut self) {
+        self.selector.query.pop();
+        self.select_link_number();
+    }
+
+    fn select_link_number(&mut self) {
+        let prefix = format!("[{}] ", self.selector.query);
+        let i = self
+            .selector
+            .options
+            .iter()
+            .position(|(_, label)| label.starts_with(&prefix));
+        if let Some(i) = i {
+            self.selector.state.select(Some(i));
+        }
+    }
+
+    fn selected_link(&self) -> Option<String> {
+        let i = self.selector.state.selected()?;
+        Some(self.selector.options[self.selector.matches[i]].0.clone())
+    }
+
+    pub fn open_link(&mut self) {
+        if let Some(url) = self.selected_link() {
+            if let Err(err) = webbrowser::open(&url) {
+                self.message = Some(format!("Couldn't open {}: {}", url, err));
+            }
+        }
+        self.input_mode = InputMode::Normal;
+    }
+
+    pub fn copy_link(&mut self) {
+        if let Some(url) = self.selected_link() {
+            self.message = Some(match copy_to_clipboard(&url) {
+                Ok(()) => format!("Copied {}", url),
+                Err(err) => format!("Couldn't copy {}: {}", url, err),
+            });
+        }
+        self.input_mode = InputMode::Normal;
     }
 
     pub fn open_marked(&self) {
@@ -353,6 +815,150 @@ impl App {
         }
     }
 
+    pub fn show_health(&mut self) {
+        let mut statuses = self.db.get_feed_statuses().unwrap_or_default();
+        let mut feeds: Vec<(Feed, FeedStatus)> = self
+            .feeds
+            .values()
+            .cloned()
+            .map(|feed| {
+                let status = statuses.remove(&feed.url).unwrap_or_default();
+                (feed, status)
+            })
+            .collect();
+
+        // Broken feeds first, then ones that have never been fetched
+        feeds.sort_by_key(|(feed, status)| {
+            (
+                Reverse(status.failures),
+                status.last_success.is_some(),
+                feed.title.to_lowercase(),
+            )
+        });
+
+        let fmt_time = |ts: Option<i64>| match ts {
+            Some(ts) => Local.timestamp(ts, 0).format("%m/%d/%y %H:%M").to_string(),
+            None => "never".to_string(),
+        };
+        self.health.set_items(
+            feeds
+                .into_iter()
+                .map(|(feed, status)| {
+                    let row = vec![
+                        feed.title,
+                        if status.failures > 0 {
+                            format!("{} failed", status.failures)
+                        } else if status.last_success.is_some() {
+                            "ok".to_string()
+                        } else {
+                            "-".to_string()
+                        },
+                        status
+                            .http_status
+                            .map(|code| code.to_string())
+                            .unwrap_or_default(),
+                        fmt_time(status.last_success),
+                        status.last_error.clone().unwrap_or_default(),
+                    ];
+                    (status, row)
+                })
+                .collect(),
+        );
+        self.health.state.select(None);
+        self.input_mode = InputMode::Health;
+    }
+
+    pub fn hide_health(&mut self) {
+        self.input_mode = InputMode::Normal;
+    }
+
+    // Generated from the active bindings, so it's never out of date
+    pub fn show_help(&mut self) {
+        let mut rows: Vec<Vec<String>> = self
+            .keymap
+            .help()
+            .into_iter()
+            .map(|(keys, name, description)| vec![keys, name.to_string(), description.to_string()])
+            .collect();
+        // Not configurable
+        for (keys, description) in &[
+            ("0-9", "Open the link with this number"),
+            ("<Backspace>", "Delete in inputs and link numbers"),
+            ("a-z etc.", "Filter the feeds popup, where typed keys aren't bindings"),
+        ] {
+            rows.push(vec![keys.to_string(), String::new(), description.to_string()]);
+        }
+        self.help.set_items(rows);
+        self.help.state.select(None);
+        self.input_mode = InputMode::Help;
+    }
+
+    pub fn hide_help(&mut self) {
+        self.input_mode = InputMode::Normal;
+    }
+
+    pub fn show_tag_selection(&mut self) {
+        let counts_by_feed = self.db.count_items().unwrap_or_default();
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for feed in self.feeds.values() {
+            let unread = counts_by_feed.get(&feed.url).map_or(0, |c| c.unread);
+            for tag in &feed.tags {
+                *counts.entry(tag).or_insert(0) += unread;
+            }
+        }
+        let mut tags: Vec<(&str, usize)> = counts.into_iter().collect();
+        tags.sort_by_key(|(tag, _)| tag.to_lowercase());
+
+        self.selector.set_options(
+            tags.into_iter()
+                .map(|(tag, count)| (tag.to_string(), format!("{} ({})", tag, count)))
+                .collect(),
+            &self.filter.tags,
+        );
+        self.input_mode = InputMode::Tags;
+    }
+
+    pub fn apply_tag_selection(&mut self) {
+        self.filter.tags = self.selector.marked.clone();
+        self.input_mode = InputMode::Normal;
+        self.table.state.select(None);
+        self.marked.clear();
+        self.load_items();
+    }
+
+    pub fn show_channel_selection(&mut self) {
+        let counts = self.db.count_items().unwrap_or_default();
+        let mut feeds: Vec<&Feed> = self.feeds.values().collect();
+        feeds.sort_by_key(|feed| feed.title.to_lowercase());
+
+        self.selector.set_options(
+            feeds
+                .into_iter()
+                .map(|feed| {
+                    let counts = counts.get(&feed.url).copied().unwrap_or_default();
+                    (
+                        feed.url.clone(),
+                        format!("{} ({} unread, {} starred)", feed.title, counts.unread, counts.starred),
+                    )
+                })
+                .collect(),
+            &self.filter.feeds,
+        );
+        self.input_mode = InputMode::Channels;
+    }
+
+    pub fn apply_channel_selection(&mut self) {
+        self.filter.feeds = self.selector.marked.clone();
+        self.input_mode = InputMode::Normal;
+        self.table.state.select(None);
+        self.marked.clear();
+        self.load_items();
+    }
+
+    pub fn cancel_selection(&mut self) {
+        self.input_mode = InputMode::Normal;
+    }
+
     pub fn toggle_read_filter(&mut self) {
         // All => Unread => Read
         self.filter.read = match self.filter.read {
@@ -368,6 +974,11 @@ impl App {
         self.load_items();
     }
 
+    pub fn toggle_keyword_filter(&mut self) {
+        self.filter.use_keywords = !self.filter.use_keywords;
+        self.load_items();
+    }
+
     pub fn toggle_starred_filter(&mut self) {
         // All => Starred => Unstarred
         self.filter.starred = match self.filter.starred {
@@ -384,14 +995,92 @@ impl App {
     }
 }
 
+// A list of options that can be marked with space,
+// for picking filter values from an overlay.
+// Options are pairs of (value, label) and can be
+// narrowed down by typing part of their label.
+pub struct Selector {
+    pub state: ListState,
+    pub options: Vec<(String, String)>,
+    pub marked: Vec<String>,
+    pub query: String,
+    pub matches: Vec<usize>,
+}
+
+impl Selector {
+    pub fn new() -> Selector {
+        Selector {
+            state: ListState::default(),
+            options: vec![],
+            marked: vec![],
+            query: String::new(),
+            matches: vec![],
+        }
+    }
+
+    pub fn set_options(&mut self, options: Vec<(String, String)>, marked: &[String]) {
+        self.options = options;
+        self.marked = marked.to_vec();
+        self.query.clear();
+        self.update_matches();
+    }
+
+    pub fn push_query(&mut self, c: char) {
+        self.query.push(c);
+        self.update_matches();
+    }
+
+    pub fn pop_query(&mut self) {
+        self.query.pop();
+        self.update_matches();
+    }
+
+    fn update_matches(&mut self) {
+        let query = self.query.to_lowercase();
+        self.matches = self
+            .options
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, label))| label.to_lowercase().contains(&query))
+            .map(|(i, _)| i)
+            .collect();
+        self.state
+            .select(if self.matches.is_empty() { None } else { Some(0) });
+    }
+
+    pub fn next(&mut self) {
+        if let Some(i) = self.state.selected() {
+            self.state.select(Some((i + 1) % self.matches.len()));
+        }
+    }
+
+    pub fn previous(&mut self) {
+        if let Some(i) = self.state.selected() {
+            self.state.select(Some((i + self.matches.len() - 1) % self.matches.len()));
+        }
+    }
+
+    pub fn toggle_selected(&mut self) {
+        if let Some(i) = self.state.selected() {
+            let value = &self.options[self.matches[i]].0;
+            if self.marked.contains(value) {
+                self.marked.retain(|v| v != value);
+            } else {
+                self.marked.push(value.clone());
+            }
+        }
+    }
+}
+
 // https://github.com/fdehau/tui-rs/blob/master/examples/table.rs
-pub struct StatefulTable {
+pub struct StatefulTable<T = Vec<String>> {
     pub state: TableState,
-    pub items: Vec<Vec<String>>,
+    pub items: Vec<T>,
 }
 
-impl StatefulTable {
-    pub fn new() -> StatefulTable {
+// Moving through an empty table selects nothing
+impl<T> StatefulTable<T> {
+    pub fn new() -> StatefulTable<T> {
         StatefulTable {
             state: TableState::default(),
             items: vec![],
@@ -399,6 +1088,9 @@ impl StatefulTable {
     }
 
     pub fn next(&mut self) {
+        if self.items.is_empty() {
+            return self.state.select(None);
+        }
         let i = match self.state.selected() {
             Some(i) => {
                 if i >= self.items.len() - 1 {
@@ -413,6 +1105,9 @@ impl StatefulTable {
     }
 
     pub fn jump_forward(&mut self, n: usize) {
+        if self.items.is_empty() {
+            return self.state.select(None);
+        }
         let i = match self.state.selected() {
             Some(i) => usize::min(i + n, self.items.len() - 1),
             None => 0,
@@ -421,6 +1116,9 @@ impl StatefulTable {
     }
 
     pub fn jump_backward(&mut self, n: usize) {
+        if self.items.is_empty() {
+            return self.state.select(None);
+        }
         let i = match self.state.selected() {
             Some(i) => {
                 if n > i {
@@ -435,6 +1133,9 @@ impl StatefulTable {
     }
 
     pub fn previous(&mut self) {
+        if self.items.is_empty() {
+            return self.state.select(None);
+        }
         let i = match self.state.selected() {
             Some(i) => {
                 if i == 0 {
@@ -448,7 +1149,37 @@ impl StatefulTable {
         self.state.select(Some(i));
     }
 
-    pub fn set_items(&mut self, items: Vec<Vec<String>>) {
+    pub fn set_items(&mut self, items: Vec<T>) {
         self.items = items;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_table_selects_nothing() {
+        let mut table: StatefulTable = StatefulTable::new();
+        table.next();
+        table.next();
+        table.previous();
+        table.jump_forward(5);
+        table.jump_backward(5);
+        assert_eq!(table.state.selected(), None);
+    }
+
+    #[test]
+    fn table_wraps_around() {
+        let mut table = StatefulTable::new();
+        table.set_items(vec![vec!["a".to_string()], vec!["b".to_string()]]);
+        table.previous();
+        assert_eq!(table.state.selected(), Some(0));
+        table.previous();
+        assert_eq!(table.state.selected(), Some(1));
+        table.next();
+        assert_eq!(table.state.selected(), Some(0));
+        table.jump_forward(5);
+        assert_eq!(table.state.selected(), Some(1));
+    }
+}
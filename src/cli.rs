@@ -0,0 +1,235 @@
+use super::conf::Config;
+use super::daemon;
+use super::db::{Database, Item};
+use super::feed::{append_feed, get_title, load_feeds, write_feeds_toml, Feed, FeedOptions};
+use super::opml;
+use super::update::{update_feeds, Progress};
+use chrono::{Local, TimeZone};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::runtime::Runtime;
+
+// Without a subcommand the TUI is started
+pub fn build() -> App<'static, 'static> {
+    App::new("rssrs")
+        .about("Terminal RSS reader")
+        .setting(AppSettings::VersionlessSubcommands)
+        .subcommand(SubCommand::with_name("update").about("Fetch all feeds once and exit"))
+        .subcommand(
+            SubCommand::with_name("daemon")
+                .about("Keep fetching feeds in the background; the reader attaches to it"),
+        )
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("List items, most recent first")
+                .arg(Arg::with_name("unread").long("unread").help("Only unread items"))
+                .arg(
+                    Arg::with_name("tag")
+                        .long("tag")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Only items from feeds with this tag"),
+                )
+                .arg(Arg::with_name("json").long("json").help("Output as JSON")),
+        )
+        .subcommand(
+            SubCommand::with_name("mark-read")
+                .about("Mark items as read by id")
+                .arg(Arg::with_name("id").required(true).multiple(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("add")
+                .about("Subscribe to a feed")
+                .arg(Arg::with_name("url").required(true))
+                .arg(
+                    Arg::with_name("title")
+                        .long("title")
+                        .takes_value(true)
+                        .help("Defaults to the feed's own title"),
+                )
+                .arg(
+                    Arg::with_name("tag")
+                        .long("tag")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import-opml")
+                .about("Add the feeds from an OPML file; folders become tags")
+                .arg(Arg::with_name("file").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("export-opml")
+                .about("Write feeds as OPML to a file or stdout")
+                .arg(Arg::with_name("file")),
+        )
+        .subcommand(
+            SubCommand::with_name("convert-feeds")
+                .about("Convert the feeds file to feeds.toml")
+                .arg(Arg::with_name("file").help("Defaults to feeds.toml next to the feeds file")),
+        )
+}
+
+pub fn run(config: &Config, command: &str, args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    match command {
+        "update" => update(config),
+        "daemon" => daemon::run(config),
+        "list" => list(config, args),
+        "mark-read" => mark_read(config, args),
+        "add" => add(config, args),
+        "import-opml" => {
+            let added = opml::import_opml(args.value_of("file").unwrap(), &config.feeds_path)?;
+            println!("Added {} feeds to {}", added, config.feeds_path.display());
+            Ok(())
+        }
+        "export-opml" => match args.value_of("file") {
+            Some(path) => opml::export_opml(&config.feeds_path, File::create(path)?),
+            None => opml::export_opml(&config.feeds_path, io::stdout()),
+        },
+        "convert-feeds" => convert_feeds(config, args),
+        _ => unreachable!(),
+    }
+}
+
+fn update(config: &Config) -> Result<(), Box<dyn Error>> {
+    let db = Database::new(&config.db_path)?;
+    let mut runtime = Runtime::new()?;
+    let feeds: Vec<Feed> = load_feeds(&config.feeds_path)?
+        .into_iter()
+        .filter(|feed| !feed.options.disabled)
+        .collect();
+
+    let mut progress = Progress::new(&feeds);
+    update_feeds(&mut runtime, &db, feeds, |update| {
+        if let Err(err) = &update.result {
+            eprintln!("{}: {}", update.feed, err);
+        }
+        progress.record(&update);
+        true
+    });
+    println!("{} new items, {} feeds failed", progress.new_item_count(), progress.failed);
+    Ok(())
+}
+
+fn convert_feeds(config: &Config, args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let path = match args.value_of("file") {
+        Some(path) => PathBuf::from(path),
+        None => config.feeds_path.with_extension("toml"),
+    };
+    if path.exists() {
+        return Err(format!("{} already exists", path.display()).into());
+    }
+    let feeds = load_feeds(&config.feeds_path)?;
+    write_feeds_toml(&feeds, File::create(&path)?)?;
+    println!("Converted {} feeds to {}", feeds.len(), path.display());
+    println!("Set `feeds_path = \"{}\"` in config.toml to use it", path.display());
+    Ok(())
+}
+
+// Items from all feeds, most recent first, with their feed's title
+pub fn list_items(
+    db: &Database,
+    feeds_path: &Path,
+    unread: bool,
+    tags: &[&str],
+) -> Result<Vec<(Item, String)>, Box<dyn Error>> {
+    let mut items: Vec<(Item, String)> = Vec::new();
+    for feed in load_feeds(feeds_path)? {
+        if !tags.is_empty() && !tags.iter().any(|tag| feed.tags.iter().any(|t| t == tag)) {
+            continue;
+        }
+        for item in db.get_feed_items(&feed.url)? {
+            if !unread || !item.read {
+                items.push((item, feed.title.clone()));
+            }
+        }
+    }
+    items.sort_by_key(|(item, _)| -item.published_at.unwrap_or(0));
+    Ok(items)
+}
+
+pub fn item_json(item: &Item, feed_title: &str) -> Value {
+    json!({
+        "id": item.id,
+        "feed": item.feed,
+        "feed_title": feed_title,
+        "title": item.title,
+        "url": item.url,
+        "author": item.author,
+        "published_at": item.published_at,
+        "read": item.read,
+        "starred": item.starred,
+    })
+}
+
+fn list(config: &Config, args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let db = Database::new(&config.db_path)?;
+    let tags: Vec<&str> = args.values_of("tag").map(|v| v.collect()).unwrap_or_default();
+    let items = list_items(&db, &config.feeds_path, args.is_present("unread"), &tags)?;
+
+    if args.is_present("json") {
+        let items: Vec<Value> = items
+            .iter()
+            .map(|(item, feed_title)| item_json(item, feed_title))
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&items)?);
+    } else {
+        for (item, feed_title) in items {
+            let pub_date = match item.published_at {
+                Some(ts) => Local.timestamp(ts, 0).format("%m/%d/%y %H:%M").to_string(),
+                None => "-".to_string(),
+            };
+            println!(
+                "{}\t{}\t{}{}\t{}\t{}",
+                item.id.unwrap_or(0),
+                pub_date,
+                if item.read { "" } else { "* " },
+                item.title.as_deref().unwrap_or("<no title>"),
+                feed_title,
+                item.url.as_deref().unwrap_or(""),
+            );
+        }
+    }
+    Ok(())
+}
+
+fn mark_read(config: &Config, args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let db = Database::new(&config.db_path)?;
+    for id in args.values_of("id").unwrap() {
+        let id: i64 = id.parse().map_err(|_| format!("Invalid item id: {}", id))?;
+        if !db.set_item_read_by_id(id, true)? {
+            return Err(format!("No item with id {}", id).into());
+        }
+    }
+    Ok(())
+}
+
+fn add(config: &Config, args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let url = args.value_of("url").unwrap().to_string();
+    if load_feeds(&config.feeds_path)?.iter().any(|feed| feed.url == url) {
+        return Err(format!("Already subscribed to {}", url).into());
+    }
+
+    let title = match args.value_of("title") {
+        Some(title) => title.to_string(),
+        None => {
+            let mut runtime = Runtime::new()?;
+            runtime.block_on(get_title(&url))?.unwrap_or_else(|| url.clone())
+        }
+    };
+    let tags = args
+        .values_of("tag")
+        .map(|v| v.map(String::from).collect())
+        .unwrap_or_default();
+    let feed = Feed { url, title, tags, options: FeedOptions::default() };
+    append_feed(&config.feeds_path, &feed)?;
+    println!("Added {} ({})", feed.title, feed.url);
+    Ok(())
+}
@@ -1,5 +1,6 @@
 use std::env::var;
 use std::fs::File;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use serde::Deserialize;
 use std::io::prelude::*;
@@ -8,25 +9,98 @@ use std::error::Error;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
+    #[serde(default = "default_db_path")]
     pub db_path: PathBuf,
+
+    #[serde(default = "default_feeds_path")]
     pub feeds_path: PathBuf,
 
+    #[serde(default = "default_socket_path")]
+    pub socket_path: PathBuf,
+
+    // Where enclosures are downloaded to, in a directory per feed
+    #[serde(default = "default_download_dir")]
+    pub download_dir: PathBuf,
+
     #[serde(default = "default_update_interval")]
     pub update_interval: u64,
 
     #[serde(default)]
-    pub keywords: Vec<String>
+    pub keywords: Vec<String>,
+
+    #[serde(default)]
+    pub views: Vec<SavedView>,
+
+    // Action names to key sequences, see `keys`
+    #[serde(default)]
+    pub keys: HashMap<String, KeyBinding>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sort {
+    Newest,
+    Oldest,
+    Title,
+    Feed,
+}
+
+impl Default for Sort {
+    fn default() -> Sort { Sort::Newest }
+}
+
+/// A named filter query, sort order and search
+/// that can be switched to from the views popup
+#[derive(Debug, Clone, Deserialize)]
+pub struct SavedView {
+    pub name: String,
+
+    #[serde(default)]
+    pub query: String,
+
+    #[serde(default)]
+    pub sort: Sort,
+
+    pub search: Option<String>,
+}
+
+/// One key sequence, or several
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum KeyBinding {
+    One(String),
+    Many(Vec<String>),
 }
 
 fn default_update_interval() -> u64 { 1200 }
 
+fn default_db_path() -> PathBuf { config_path("rssrs.db") }
+
+// feeds.toml if there is one, otherwise the older feeds.txt
+fn default_feeds_path() -> PathBuf {
+    let path = config_path("feeds.toml");
+    if path.exists() {
+        path
+    } else {
+        config_path("feeds.txt")
+    }
+}
+
+fn default_socket_path() -> PathBuf { config_path("rssrs.sock") }
+
+fn default_download_dir() -> PathBuf { home_dir().join("Downloads/rssrs") }
+
 impl Default for Config {
     fn default() -> Config {
         Config {
             update_interval: 1200,
-            db_path: config_path("rssrs.db"),
-            feeds_path: config_path("feeds.txt"),
-            keywords: Vec::new()
+            db_path: default_db_path(),
+            feeds_path: default_feeds_path(),
+            socket_path: default_socket_path(),
+            download_dir: default_download_dir(),
+            keywords: Vec::new(),
+            views: Vec::new(),
+            keys: HashMap::new(),
         }
     }
 }
@@ -45,10 +119,13 @@ impl Config {
     }
 }
 
-fn config_path<P>(path: P) -> PathBuf where P: AsRef<Path> {
-    let home = match var("HOME") {
+fn home_dir() -> PathBuf {
+    match var("HOME") {
         Ok(path) => PathBuf::from(path),
         Err(_) => PathBuf::from(".")
-    };
-    home.join(".config/rssrs").join(path)
+    }
+}
+
+fn config_path<P>(path: P) -> PathBuf where P: AsRef<Path> {
+    home_dir().join(".config/rssrs").join(path)
 }
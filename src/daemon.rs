@@ -0,0 +1,307 @@
+use super::cli::{item_json, list_items};
+use super::conf::Config;
+use super::db::Database;
+use super::feed::load_feeds;
+use super::update::{update_feeds, Progress, Schedule};
+use chrono::Utc;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::fs;
+use std::io::prelude::*;
+use std::io::{self, BufReader};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+/*
+ * The daemon keeps feeds updated in the background and answers
+ * line-delimited JSON-RPC 2.0 requests on a unix socket:
+ *
+ *   refresh {feed?}               update all feeds, or just one, now
+ *   status                        see `Status`
+ *   list {unread?, tag?}          same items as `rssrs list --json`
+ *   mark {id, read?, starred?}    returns whether the item exists
+ *
+ * Requests without an id are notifications and aren't answered.
+ */
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Status {
+    pub updating: bool,
+    pub last_update: Option<i64>,
+    // Completed update cycles, so clients can tell when new items arrived
+    pub cycles: u64,
+    // Of the current cycle, or the last one when not updating
+    pub progress: Progress,
+}
+
+#[derive(Deserialize)]
+struct Request {
+    // `None` for notifications; a request's id can also be null
+    #[serde(default, deserialize_with = "present")]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+#[derive(Serialize, Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn server<E: ToString>(err: E) -> RpcError {
+        RpcError { code: SERVER_ERROR, message: err.to_string() }
+    }
+}
+
+#[derive(Deserialize)]
+struct RefreshParams {
+    feed: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ListParams {
+    #[serde(default)]
+    unread: bool,
+    #[serde(default)]
+    tag: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct MarkParams {
+    id: i64,
+    read: Option<bool>,
+    starred: Option<bool>,
+}
+
+pub fn run(config: &Config) -> Result<(), Box<dyn Error>> {
+    if Client::connect(&config.socket_path).is_ok() {
+        return Err(format!("A daemon is already listening on {}", config.socket_path.display()).into());
+    }
+    // Left behind by a daemon that didn't shut down cleanly
+    if config.socket_path.exists() {
+        fs::remove_file(&config.socket_path)?;
+    }
+    if let Some(dir) = config.socket_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // Before listening, so there's never a daemon that can't fetch
+    let runtime = Runtime::new()?;
+    let db = Database::new(&config.db_path)?;
+    let listener = UnixListener::bind(&config.socket_path)?;
+
+    let status = Arc::new(Mutex::new(Status::default()));
+    let (refresh_tx, refresh_rx) = mpsc::channel();
+    {
+        let config = config.clone();
+        let status = status.clone();
+        thread::spawn(move || fetch_loop(runtime, db, config, status, refresh_rx));
+    }
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("{}", err);
+                continue;
+            }
+        };
+        let config = config.clone();
+        let status = status.clone();
+        let refresh = refresh_tx.clone();
+        thread::spawn(move || {
+            if let Err(err) = serve(stream, &config, &status, &refresh) {
+                eprintln!("{}", err);
+            }
+        });
+    }
+    Ok(())
+}
+
+// Update each feed every `update_interval` (or its own interval), or sooner when asked to
+fn fetch_loop(
+    mut runtime: Runtime,
+    db: Database,
+    config: Config,
+    status: Arc<Mutex<Status>>,
+    refresh_rx: mpsc::Receiver<Option<String>>,
+) {
+    let mut schedule = Schedule::new(Duration::from_secs(config.update_interval));
+    let mut refresh = Some(None);
+    loop {
+        let feeds = match load_feeds(&config.feeds_path) {
+            Ok(feeds) => schedule.take_due(feeds, refresh),
+            Err(err) => {
+                eprintln!("{}", err);
+                Vec::new()
+            }
+        };
+        if !feeds.is_empty() {
+            {
+                let mut status = status.lock().unwrap();
+                status.updating = true;
+                status.progress = Progress::new(&feeds);
+            }
+            update_feeds(&mut runtime, &db, feeds, |update| {
+                status.lock().unwrap().progress.record(&update);
+                true
+            });
+            {
+                let mut status = status.lock().unwrap();
+                status.updating = false;
+                status.last_update = Some(Utc::now().timestamp());
+                status.cycles += 1;
+            }
+        }
+
+        refresh = match refresh_rx.recv_timeout(schedule.wait()) {
+            Ok(feed) => Some(feed),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+    }
+}
+
+fn serve(
+    stream: UnixStream,
+    config: &Config,
+    status: &Mutex<Status>,
+    refresh: &mpsc::Sender<Option<String>>,
+) -> Result<(), Box<dyn Error>> {
+    let db = Database::new(&config.db_path)?;
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (id, result) = match serde_json::from_str::<Request>(&line) {
+            Ok(req) => {
+                let result = handle(&req.method, req.params, config, &db, status, refresh);
+                match req.id {
+                    Some(id) => (id, result),
+                    None => continue,
+                }
+            }
+            Err(err) => (Value::Null, Err(RpcError { code: PARSE_ERROR, message: err.to_string() })),
+        };
+        let response = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+        };
+        writeln!(writer, "{}", response)?;
+    }
+    Ok(())
+}
+
+fn handle(
+    method: &str,
+    params: Value,
+    config: &Config,
+    db: &Database,
+    status: &Mutex<Status>,
+    refresh: &mpsc::Sender<Option<String>>,
+) -> Result<Value, RpcError> {
+    match method {
+        "refresh" => {
+            let params: RefreshParams = parse_params(params)?;
+            refresh.send(params.feed).map_err(RpcError::server)?;
+            Ok(json!(true))
+        }
+        "status" => Ok(json!(*status.lock().unwrap())),
+        "list" => {
+            let params: ListParams = parse_params(params)?;
+            let tags: Vec<&str> = params.tag.iter().map(String::as_str).collect();
+            let items = list_items(db, &config.feeds_path, params.unread, &tags)
+                .map_err(RpcError::server)?;
+            let items = items
+                .iter()
+                .map(|(item, feed_title)| item_json(item, feed_title))
+                .collect();
+            Ok(Value::Array(items))
+        }
+        "mark" => {
+            let params: MarkParams = parse_params(params)?;
+            // Marking without saying how means marking as read
+            let read = match (params.read, params.starred) {
+                (None, None) => Some(true),
+                (read, _) => read,
+            };
+            let mut found = false;
+            if let Some(read) = read {
+                found |= db.set_item_read_by_id(params.id, read).map_err(RpcError::server)?;
+            }
+            if let Some(starred) = params.starred {
+                found |= db.set_item_starred_by_id(params.id, starred).map_err(RpcError::server)?;
+            }
+            Ok(json!(found))
+        }
+        _ => Err(RpcError {
+            code: METHOD_NOT_FOUND,
+            message: format!("Unknown method: {}", method),
+        }),
+    }
+}
+
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    // Params may be left out for methods without required ones
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|err| RpcError {
+        code: INVALID_PARAMS,
+        message: err.to_string(),
+    })
+}
+
+/// A connection to a running daemon
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    next_id: u64,
+}
+
+impl Client {
+    pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<Client> {
+        let writer = UnixStream::connect(path)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(Client { reader, writer, next_id: 0 })
+    }
+
+    pub fn call(&mut self, method: &str, params: Value) -> Result<Value, Box<dyn Error>> {
+        self.next_id += 1;
+        let request = json!({ "jsonrpc": "2.0", "id": self.next_id, "method": method, "params": params });
+        writeln!(self.writer, "{}", request)?;
+
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err("The daemon closed the connection".into());
+        }
+        let mut response: Value = serde_json::from_str(&line)?;
+        if let Some(error) = response.get("error") {
+            let error: RpcError = serde_json::from_value(error.clone())?;
+            return Err(format!("{} ({})", error.message, error.code).into());
+        }
+        Ok(response["result"].take())
+    }
+
+    pub fn status(&mut self) -> Result<Status, Box<dyn Error>> {
+        Ok(serde_json::from_value(self.call("status", Value::Null)?)?)
+    }
+}
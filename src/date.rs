@@ -0,0 +1,237 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+
+// Formats seen in the wild, tried after the standard ones.
+// Named timezones and weekdays have been normalized away by then.
+const FORMATS: &[&str] = &[
+    "%d %b %Y %H:%M:%S %z",
+    "%d %b %Y %H:%M %z",
+    "%d %b %y %H:%M:%S %z",
+    "%d %b %y %H:%M %z",
+    "%b %d %Y %H:%M:%S %z",
+    "%Y-%m-%dT%H:%M:%S%.f%z",
+    "%Y-%m-%dT%H:%M%z",
+    "%Y-%m-%d %H:%M:%S%.f %z",
+    "%Y-%m-%d %H:%M:%S%.f%z",
+];
+
+// Without a timezone, which is taken to be UTC
+const NAIVE_FORMATS: &[&str] = &[
+    "%d %b %Y %H:%M:%S",
+    "%d %b %Y %H:%M",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%d %H:%M",
+];
+
+const DATE_FORMATS: &[&str] = &["%Y-%m-%d", "%d %b %Y", "%b %d, %Y", "%b %d %Y", "%Y/%m/%d"];
+
+// Offsets of timezone abbreviations in feeds; some are ambiguous,
+// so these are the most likely ones
+const TIMEZONES: &[(&str, &str)] = &[
+    ("Z", "+0000"),
+    ("UT", "+0000"),
+    ("UTC", "+0000"),
+    ("GMT", "+0000"),
+    ("WET", "+0000"),
+    ("WEST", "+0100"),
+    ("BST", "+0100"),
+    ("CET", "+0100"),
+    ("CEST", "+0200"),
+    ("MET", "+0100"),
+    ("MEST", "+0200"),
+    ("EET", "+0200"),
+    ("EEST", "+0300"),
+    ("MSK", "+0300"),
+    ("IST", "+0530"),
+    ("SGT", "+0800"),
+    ("HKT", "+0800"),
+    ("AWST", "+0800"),
+    ("JST", "+0900"),
+    ("KST", "+0900"),
+    ("ACST", "+0930"),
+    ("AEST", "+1000"),
+    ("AEDT", "+1100"),
+    ("NZST", "+1200"),
+    ("NZDT", "+1300"),
+    ("EST", "-0500"),
+    ("EDT", "-0400"),
+    ("CST", "-0600"),
+    ("CDT", "-0500"),
+    ("MST", "-0700"),
+    ("MDT", "-0600"),
+    ("PST", "-0800"),
+    ("PDT", "-0700"),
+    ("AKST", "-0900"),
+    ("AKDT", "-0800"),
+    ("HST", "-1000"),
+];
+
+/// Parse a feed's date into a timestamp, trying RFC 2822 and RFC 3339
+/// first, then common variations: named timezones, missing or wrong
+/// weekdays, missing timezones or times, full month names, etc.
+pub fn parse_date(text: &str) -> Option<i64> {
+    let text = text.trim();
+    if let Ok(dt) = DateTime::parse_from_rfc2822(text) {
+        return Some(dt.timestamp());
+    }
+    if let Ok(dt) = DateTime::parse_from_rfc3339(text) {
+        return Some(dt.timestamp());
+    }
+
+    let text = normalize(text);
+    for format in FORMATS {
+        if let Ok(dt) = DateTime::parse_from_str(&text, format) {
+            return Some(dt.timestamp());
+        }
+    }
+    for format in NAIVE_FORMATS {
+        if let Ok(dt) = NaiveDateTime::parse_from_str(&text, format) {
+            return Some(dt.timestamp());
+        }
+    }
+    for format in DATE_FORMATS {
+        if let Ok(date) = NaiveDate::parse_from_str(&text, format) {
+            return Some(date.and_hms(0, 0, 0).timestamp());
+        }
+    }
+    None
+}
+
+const MONTHS: &[&str] = &[
+    "january", "february", "march", "april", "may", "june",
+    "july", "august", "september", "october", "november", "december",
+];
+
+// Drop the weekday, which is often wrong, shorten month names
+// and replace a named timezone with its offset
+fn normalize(text: &str) -> String {
+    let text = text.replace(" at ", " ");
+    let mut words: Vec<&str> = text
+        .split_whitespace()
+        .map(|word| {
+            let name = word.trim_end_matches(',').to_lowercase();
+            if name.len() > 3 && MONTHS.contains(&name.as_str()) {
+                &word[..3]
+            } else {
+                word
+            }
+        })
+        .collect();
+    if let Some(first) = words.first() {
+        let weekday = first.trim_end_matches(',');
+        if weekday.len() >= 3 && weekday.chars().all(|c| c.is_ascii_alphabetic()) && is_weekday(weekday) {
+            words.remove(0);
+        }
+    }
+
+    // e.g. `2021-03-01T10:00:00Z` or `... 10:00:00 EST`
+    let mut text = words.join(" ");
+    if let Some(rest) = text.strip_suffix('Z') {
+        if rest.ends_with(|c: char| c.is_ascii_digit()) {
+            text = format!("{}+0000", rest);
+        }
+    }
+    if let Some(i) = text.rfind(' ') {
+        let zone = text[i + 1..].trim_start_matches('(').trim_end_matches(')');
+        if let Some((_, offset)) = TIMEZONES.iter().find(|(name, _)| name.eq_ignore_ascii_case(zone)) {
+            text = format!("{} {}", &text[..i], offset);
+        }
+    }
+    text
+}
+
+fn is_weekday(word: &str) -> bool {
+    let word = word.to_lowercase();
+    ["mon", "tue", "wed", "thu", "fri", "sat", "sun"]
+        .iter()
+        .any(|day| word.starts_with(day))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2021-03-01T10:00:00Z
+    const TIMESTAMP: i64 = 1_614_592_800;
+    const MIDNIGHT: i64 = TIMESTAMP - 10 * 60 * 60;
+
+    fn assert_all(dates: &[&str], expected: i64) {
+        for date in dates {
+            assert_eq!(parse_date(date), Some(expected), "{}", date);
+        }
+    }
+
+    #[test]
+    fn standard_formats() {
+        assert_all(
+            &[
+                "Mon, 01 Mar 2021 10:00:00 +0000",
+                "Mon, 01 Mar 2021 10:00:00 GMT",
+                "2021-03-01T10:00:00Z",
+                "2021-03-01T10:00:00.123Z",
+                "2021-03-01T11:00:00+01:00",
+            ],
+            TIMESTAMP,
+        );
+    }
+
+    #[test]
+    fn named_timezones() {
+        assert_all(
+            &[
+                "Mon, 1 Mar 2021 05:00:00 EST",
+                "Mon, 01 Mar 2021 11:00:00 CET",
+                "Mon, 01 Mar 2021 19:00:00 JST",
+                "Mon, 01 Mar 2021 02:00:00 pst",
+                "Mon, 01 Mar 2021 10:00:00 (UTC)",
+                "2021-03-01T10:00:00 UTC",
+            ],
+            TIMESTAMP,
+        );
+    }
+
+    #[test]
+    fn weekdays_and_month_names() {
+        assert_all(
+            &[
+                "01 Mar 2021 10:00:00 +0000",
+                "Fri, 01 Mar 2021 10:00:00 +0000",
+                "Monday, 01 March 2021 11:00:00 CET",
+                "Mon, 01 Mar 2021 10:00 GMT",
+                "01 Mar 21 10:00:00 +0000",
+                "March 01 2021 10:00:00 +0000",
+                "Monday, 01 March 2021 at 10:00 GMT",
+                "  Mon,  01 Mar 2021 10:00:00  +0000 ",
+            ],
+            TIMESTAMP,
+        );
+    }
+
+    #[test]
+    fn without_timezones() {
+        assert_all(
+            &[
+                "2021-03-01T10:00:00",
+                "2021-03-01T10:00",
+                "2021-03-01 10:00:00",
+                "2021-03-01 10:00",
+                "2021-03-01 10:00:00 +0000",
+                "01 Mar 2021 10:00:00",
+            ],
+            TIMESTAMP,
+        );
+    }
+
+    #[test]
+    fn dates_without_times() {
+        assert_all(&["2021-03-01", "2021/03/01", "01 Mar 2021", "March 1, 2021", "Mar 1 2021"], MIDNIGHT);
+    }
+
+    #[test]
+    fn invalid() {
+        for date in &["", "garbage", "yesterday", "2021-13-01", "Mon, 32 Mar 2021 10:00:00 +0000", "10:00:00"] {
+            assert_eq!(parse_date(date), None, "{}", date);
+        }
+    }
+}
@@ -1,17 +1,123 @@
-use rusqlite::{params, Connection, Result};
+use super::migrations::{migrate, LEGACY_GUID_PREFIX};
+use rusqlite::{params, Connection, Result, Row};
+use std::collections::HashMap;
+use std::error::Error;
 use std::fs::{create_dir_all, File};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const ITEM_COLUMNS: &str =
+    "item.url, item.read, item.starred, item.feed, item.title, item.published_at,
+     item.retrieved_at, item.description, item.author, item.guid, item.id, item.content,
+     item.article";
+
+const ENCLOSURE_COLUMNS: &str =
+    "enclosure.id, enclosure.url, enclosure.mime_type, enclosure.length, enclosure.duration,
+     download.path, download.state, download.downloaded, download.total, download.error,
+     download.etag, download.last_modified";
 
 #[derive(Debug)]
 pub struct Item {
+    // None until the item is saved
+    pub id: Option<i64>,
     pub read: bool,
     pub starred: bool,
     pub feed: String,
+    pub guid: String,
     pub retrieved_at: i64,
     pub title: Option<String>,
     pub url: Option<String>,
+    pub author: Option<String>,
+    // Undated items are stored without one, and dated by when they were
+    // first seen when they're loaded
     pub published_at: Option<i64>,
+    // The summary, and the full article if the feed has it
     pub description: Option<String>,
+    pub content: Option<String>,
+    // Extracted from the item's page when asked for
+    pub article: Option<String>,
+    pub enclosures: Vec<Enclosure>,
+}
+
+/// A file attached to an item, e.g. a podcast episode
+#[derive(Debug, Clone)]
+pub struct Enclosure {
+    // None until the enclosure is saved
+    pub id: Option<i64>,
+    pub url: String,
+    pub mime_type: Option<String>,
+    // In bytes
+    pub length: Option<i64>,
+    // In seconds, from `itunes:duration`
+    pub duration: Option<i64>,
+    // Once it's been queued for download
+    pub download: Option<Download>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DownloadState {
+    Queued,
+    Downloading,
+    Done,
+    Failed,
+}
+
+impl DownloadState {
+    fn as_str(self) -> &'static str {
+        match self {
+            DownloadState::Queued => "queued",
+            DownloadState::Downloading => "downloading",
+            DownloadState::Done => "done",
+            DownloadState::Failed => "failed",
+        }
+    }
+
+    fn parse(state: &str) -> DownloadState {
+        match state {
+            "queued" => DownloadState::Queued,
+            "downloading" => DownloadState::Downloading,
+            "done" => DownloadState::Done,
+            _ => DownloadState::Failed,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Download {
+    pub enclosure: i64,
+    pub url: String,
+    pub path: PathBuf,
+    pub state: DownloadState,
+    // In bytes
+    pub downloaded: i64,
+    pub total: Option<i64>,
+    pub error: Option<String>,
+    // Validators from the response the download started with
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// Validators from the last successful fetch of a feed,
+/// sent back so unchanged feeds aren't downloaded again
+#[derive(Debug, Default)]
+pub struct HttpCache {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// Outcome of the most recent fetches of a feed
+#[derive(Debug, Default)]
+pub struct FeedStatus {
+    pub last_success: Option<i64>,
+    pub last_error: Option<String>,
+    pub http_status: Option<u16>,
+    pub failures: u32,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ItemCounts {
+    pub unread: usize,
+    pub starred: usize,
 }
 
 pub struct Database {
@@ -19,79 +125,372 @@ pub struct Database {
 }
 
 impl Database {
-    pub fn new<P>(path: P) -> Database
+    pub fn new<P>(path: P) -> Result<Database, Box<dyn Error>>
     where
         P: AsRef<Path>,
     {
         let path_buf = path.as_ref().to_path_buf();
         if !path_buf.exists() {
-            create_dir_all(path_buf.parent().unwrap()).unwrap();
-            File::create(&path).unwrap();
+            create_dir_all(path_buf.parent().unwrap())?;
+            File::create(&path)?;
         }
-        let conn = Connection::open(path).unwrap();
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS item (
-                      url             TEXT PRIMARY KEY,
-                      read            INTEGER DEFAULT 0,
-                      starred         INTEGER DEFAULT 0,
-                      feed            TEXT,
-                      title           TEXT,
-                      published_at    INTEGER,
-                      retrieved_at    INTEGER,
-                      description     TEXT
-                      )",
-            params![],
-        )
-        .unwrap();
+        let mut conn = Connection::open(&path)?;
+
+        // The reader, daemon and CLI may all be using the database
+        conn.busy_timeout(Duration::from_secs(5))?;
+        migrate(&mut conn, &path)?;
 
-        Database { conn }
+        Ok(Database { conn })
     }
 
-    pub fn add_item(&self, item: &Item) -> Result<()> {
-        // Ignore unique constraint conflicts
+    // Whether any items carried over from before guids were tracked are left
+    pub fn has_legacy_items(&self) -> Result<bool> {
+        self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM item WHERE substr(guid, 1, length(?1)) == ?1)",
+            params![LEGACY_GUID_PREFIX],
+            |row| row.get(0),
+        )
+    }
+
+    // Give an item carried over from before guids were tracked its guid,
+    // matching it by url, or title if it has none, like the migration did
+    pub fn adopt_legacy_item(&self, item: &Item) -> Result<()> {
+        let key = item.url.as_deref().or(item.title.as_deref()).unwrap_or("");
         self.conn.execute(
-            "INSERT OR IGNORE INTO item (url, feed, title, published_at, retrieved_at, description) VALUES (?, ?, ?, ?, ?, ?)",
-            params![item.url, item.feed, item.title, item.published_at, item.retrieved_at, item.description],
+            "UPDATE OR IGNORE item SET guid=? WHERE feed == ? AND guid == ?",
+            params![item.guid, item.feed, format!("{}{}", LEGACY_GUID_PREFIX, key)],
         )?;
         Ok(())
     }
 
+    // Returns whether the item is new
+    pub fn add_item(&self, item: &Item, feed_title: &str) -> Result<bool> {
+        // Ignore unique constraint conflicts
+        let inserted = self.conn.execute(
+            "INSERT OR IGNORE INTO item (feed, guid, url, title, author, published_at, retrieved_at, description, content) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            params![item.feed, item.guid, item.url, item.title, item.author, item.published_at, item.retrieved_at, item.description, item.content],
+        )?;
+
+        // Keep the search index in sync, indexing the full article if there is one
+        let item_id = self.conn.last_insert_rowid();
+        if inserted > 0 {
+            self.conn.execute(
+                "INSERT INTO item_fts (rowid, title, description, feed_title) VALUES (?, ?, ?, ?)",
+                params![
+                    item_id,
+                    item.title.as_deref().unwrap_or(""),
+                    item.content.as_deref().or(item.description.as_deref()).unwrap_or(""),
+                    feed_title
+                ],
+            )?;
+        }
+
+        // Also for existing items, which may be from before enclosures were saved
+        if !item.enclosures.is_empty() {
+            let item_id: i64 = if inserted > 0 {
+                item_id
+            } else {
+                self.conn.query_row(
+                    "SELECT id FROM item WHERE feed == ? AND guid == ?",
+                    params![item.feed, item.guid],
+                    |row| row.get(0),
+                )?
+            };
+            for enclosure in &item.enclosures {
+                self.conn.execute(
+                    "INSERT OR IGNORE INTO enclosure (item, url, mime_type, length, duration) VALUES (?, ?, ?, ?, ?)",
+                    params![item_id, enclosure.url, enclosure.mime_type, enclosure.length, enclosure.duration],
+                )?;
+            }
+        }
+        Ok(inserted > 0)
+    }
+
     pub fn set_item_read(&self, item: &Item, read: bool) -> Result<()> {
         self.conn.execute(
-            "UPDATE item SET read=? WHERE url == ?",
-            params![read, item.url],
+            "UPDATE item SET read=? WHERE feed == ? AND guid == ?",
+            params![read, item.feed, item.guid],
         )?;
         Ok(())
     }
 
     pub fn set_item_starred(&self, item: &Item, starred: bool) -> Result<()> {
         self.conn.execute(
-            "UPDATE item SET starred=? WHERE url == ?",
-            params![starred, item.url],
+            "UPDATE item SET starred=? WHERE feed == ? AND guid == ?",
+            params![starred, item.feed, item.guid],
         )?;
         Ok(())
     }
 
+    pub fn set_item_read_by_id(&self, id: i64, read: bool) -> Result<bool> {
+        let updated = self
+            .conn
+            .execute("UPDATE item SET read=? WHERE id == ?", params![read, id])?;
+        Ok(updated > 0)
+    }
+
+    // Also replaces the item's body in the search index
+    pub fn set_item_article(&self, id: i64, article: &str) -> Result<()> {
+        self.conn
+            .execute("UPDATE item SET article=? WHERE id == ?", params![article, id])?;
+        self.conn.execute(
+            "UPDATE item_fts SET description=? WHERE rowid == ?",
+            params![article, id],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_item_starred_by_id(&self, id: i64, starred: bool) -> Result<bool> {
+        let updated = self
+            .conn
+            .execute("UPDATE item SET starred=? WHERE id == ?", params![starred, id])?;
+        Ok(updated > 0)
+    }
+
     pub fn get_feed_items(&self, feed: &str) -> Result<Vec<Item>> {
-        let mut stmt = self.conn.prepare("SELECT * FROM item WHERE feed == ?")?;
-        let items = stmt
-            .query_map(&[feed], |row| {
-                Ok(Item {
-                    url: row.get(0)?,
-                    read: row.get(1)?,
-                    starred: row.get(2)?,
-                    feed: row.get(3)?,
-                    title: row.get(4)?,
-                    published_at: row.get(5)?,
-                    retrieved_at: row.get(6)?,
-                    description: row.get(7)?,
-                })
-            })?
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM item WHERE feed == ?",
+            ITEM_COLUMNS
+        ))?;
+        let mut items: Vec<Item> = stmt
+            .query_map(&[feed], item_from_row)?
             .filter_map(Result::ok)
             .collect();
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {}, enclosure.item FROM enclosure
+             JOIN item ON item.id == enclosure.item
+             LEFT JOIN download ON download.enclosure == enclosure.id
+             WHERE item.feed == ? ORDER BY enclosure.id",
+            ENCLOSURE_COLUMNS
+        ))?;
+        let mut enclosures: HashMap<i64, Vec<Enclosure>> = HashMap::new();
+        for row in stmt.query_map(&[feed], |row| Ok((row.get(12)?, enclosure_from_row(row)?)))? {
+            let (item_id, enclosure) = row?;
+            enclosures.entry(item_id).or_default().push(enclosure);
+        }
+        for item in &mut items {
+            if let Some(item_enclosures) = item.id.and_then(|id| enclosures.remove(&id)) {
+                item.enclosures = item_enclosures;
+            }
+        }
         Ok(items)
     }
 
+    pub fn get_item_enclosures(&self, item_id: i64) -> Result<Vec<Enclosure>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM enclosure
+             LEFT JOIN download ON download.enclosure == enclosure.id
+             WHERE enclosure.item == ? ORDER BY enclosure.id",
+            ENCLOSURE_COLUMNS
+        ))?;
+        let enclosures = stmt
+            .query_map(params![item_id], enclosure_from_row)?
+            .filter_map(Result::ok)
+            .collect();
+        Ok(enclosures)
+    }
+
+    /// Queue an enclosure to be downloaded to `path`,
+    /// or to be retried if it failed. Finished downloads are left alone.
+    pub fn queue_download(&self, enclosure: i64, path: &Path) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO download (enclosure, path, state, downloaded) VALUES (?1, ?2, 'queued', 0)
+             ON CONFLICT(enclosure) DO UPDATE SET state='queued', error=NULL WHERE state != 'done'",
+            params![enclosure, path.to_string_lossy()],
+        )?;
+        Ok(())
+    }
+
+    // Queued or interrupted downloads
+    pub fn get_pending_downloads(&self) -> Result<Vec<Download>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT download.enclosure, enclosure.url, download.path, download.state,
+                    download.downloaded, download.total, download.error, download.etag,
+                    download.last_modified
+             FROM download JOIN enclosure ON enclosure.id == download.enclosure
+             WHERE download.state IN ('queued', 'downloading') ORDER BY download.enclosure",
+        )?;
+        let downloads = stmt
+            .query_map(params![], download_from_row)?
+            .filter_map(Result::ok)
+            .collect();
+        Ok(downloads)
+    }
+
+    pub fn update_download(&self, download: &Download) -> Result<()> {
+        self.conn.execute(
+            "UPDATE download SET state=?, downloaded=?, total=?, error=?, etag=?, last_modified=?
+             WHERE enclosure == ?",
+            params![
+                download.state.as_str(),
+                download.downloaded,
+                download.total,
+                download.error,
+                download.etag,
+                download.last_modified,
+                download.enclosure
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Delete a feed's unstarred items published before `before`,
+    /// or beyond the newest `keep` of them. Items with downloads are kept
+    /// like starred ones, so their files are never left behind untracked.
+    /// So are undated ones, which the next fetch would only add again.
+    pub fn prune_items(&self, feed: &str, before: Option<i64>, keep: Option<usize>) -> Result<usize> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, published_at FROM item WHERE feed == ? AND starred == 0
+             AND published_at IS NOT NULL
+             AND NOT EXISTS (
+                SELECT 1 FROM enclosure JOIN download ON download.enclosure == enclosure.id
+                WHERE enclosure.item == item.id)
+             ORDER BY published_at DESC",
+        )?;
+        let items: Vec<(i64, i64)> = stmt
+            .query_map(&[feed], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .filter_map(Result::ok)
+            .collect();
+
+        let mut pruned = 0;
+        for (i, (id, published_at)) in items.into_iter().enumerate() {
+            let too_old = match before {
+                Some(before) => published_at < before,
+                None => false,
+            };
+            let too_many = match keep {
+                Some(keep) => i >= keep,
+                None => false,
+            };
+            if too_old || too_many {
+                self.conn.execute("DELETE FROM item_fts WHERE rowid == ?", params![id])?;
+                self.conn.execute("DELETE FROM enclosure WHERE item == ?", params![id])?;
+                pruned += self.conn.execute("DELETE FROM item WHERE id == ?", params![id])?;
+            }
+        }
+        Ok(pruned)
+    }
+
+    /// Full-text search across all items, best matches first.
+    /// Each item comes with a snippet of where it matched.
+    pub fn search_items(&self, query: &str, limit: u32) -> Result<Vec<(Item, String)>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {}, snippet(item_fts, -1, '', '', '...', 16)
+             FROM item_fts JOIN item ON item.id == item_fts.rowid
+             WHERE item_fts MATCH ? ORDER BY rank LIMIT ?",
+            ITEM_COLUMNS
+        ))?;
+        let mut results: Vec<(Item, String)> = stmt
+            .query_map(params![fts_query(query), limit], |row| {
+                Ok((item_from_row(row)?, row.get(13)?))
+            })?
+            .filter_map(Result::ok)
+            .collect();
+        for (item, _) in &mut results {
+            if let Some(id) = item.id {
+                item.enclosures = self.get_item_enclosures(id)?;
+            }
+        }
+        Ok(results)
+    }
+
+    pub fn sync_feed_titles<'a, I>(&self, feeds: I) -> Result<()>
+    where
+        I: Iterator<Item = (&'a str, &'a str)>,
+    {
+        for (url, title) in feeds {
+            self.conn.execute(
+                "UPDATE item_fts SET feed_title = ?1
+                 WHERE rowid IN (SELECT id FROM item WHERE feed == ?2) AND feed_title != ?1",
+                params![title, url],
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn count_items(&self) -> Result<HashMap<String, ItemCounts>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT feed, sum(read == 0), sum(starred != 0) FROM item GROUP BY feed",
+        )?;
+        let counts = stmt
+            .query_map(params![], |row| {
+                Ok((
+                    row.get(0)?,
+                    ItemCounts {
+                        unread: row.get::<_, i64>(1)? as usize,
+                        starred: row.get::<_, i64>(2)? as usize,
+                    },
+                ))
+            })?
+            .filter_map(Result::ok)
+            .collect();
+        Ok(counts)
+    }
+
+    pub fn get_http_cache(&self, feed: &str) -> Result<HttpCache> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT etag, last_modified FROM http_cache WHERE feed == ?")?;
+        let mut rows = stmt.query_map(&[feed], |row| {
+            Ok(HttpCache {
+                etag: row.get(0)?,
+                last_modified: row.get(1)?,
+            })
+        })?;
+        match rows.next() {
+            Some(cache) => cache,
+            None => Ok(HttpCache::default()),
+        }
+    }
+
+    pub fn set_http_cache(&self, feed: &str, cache: &HttpCache) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO http_cache (feed, etag, last_modified) VALUES (?, ?, ?)",
+            params![feed, cache.etag, cache.last_modified],
+        )?;
+        Ok(())
+    }
+
+    pub fn record_fetch_success(&self, feed: &str, http_status: u16, time: i64) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO feed_status (feed, last_success, http_status, failures) VALUES (?1, ?2, ?3, 0)
+             ON CONFLICT(feed) DO UPDATE SET last_success=?2, http_status=?3, failures=0",
+            params![feed, time, http_status],
+        )?;
+        Ok(())
+    }
+
+    pub fn record_fetch_error(&self, feed: &str, http_status: Option<u16>, error: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO feed_status (feed, last_error, http_status, failures) VALUES (?1, ?2, ?3, 1)
+             ON CONFLICT(feed) DO UPDATE SET last_error=?2, http_status=?3, failures=failures+1",
+            params![feed, error, http_status],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_feed_statuses(&self) -> Result<HashMap<String, FeedStatus>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT feed, last_success, last_error, http_status, failures FROM feed_status",
+        )?;
+        let statuses = stmt
+            .query_map(params![], |row| {
+                Ok((
+                    row.get(0)?,
+                    FeedStatus {
+                        last_success: row.get(1)?,
+                        last_error: row.get(2)?,
+                        http_status: row.get(3)?,
+                        failures: row.get(4)?,
+                    },
+                ))
+            })?
+            .filter_map(Result::ok)
+            .collect();
+        Ok(statuses)
+    }
+
     pub fn last_update(&self) -> Result<i64> {
         self.conn
             .query_row("SELECT max(retrieved_at) FROM item", params![], |row| {
@@ -99,3 +498,130 @@ impl Database {
             })
     }
 }
+
+fn item_from_row(row: &Row) -> Result<Item> {
+    let published_at: Option<i64> = row.get(5)?;
+    let retrieved_at = row.get(6)?;
+    Ok(Item {
+        url: row.get(0)?,
+        read: row.get(1)?,
+        starred: row.get(2)?,
+        feed: row.get(3)?,
+        title: row.get(4)?,
+        published_at: published_at.or(Some(retrieved_at)),
+        retrieved_at,
+        description: row.get(7)?,
+        author: row.get(8)?,
+        guid: row.get(9)?,
+        id: row.get(10)?,
+        content: row.get(11)?,
+        article: row.get(12)?,
+        enclosures: Vec::new(),
+    })
+}
+
+fn enclosure_from_row(row: &Row) -> Result<Enclosure> {
+    let id: i64 = row.get(0)?;
+    let url: String = row.get(1)?;
+    let download = match row.get::<_, Option<String>>(5)? {
+        Some(path) => Some(Download {
+            enclosure: id,
+            url: url.clone(),
+            path: PathBuf::from(path),
+            state: DownloadState::parse(&row.get::<_, String>(6)?),
+            downloaded: row.get(7)?,
+            total: row.get(8)?,
+            error: row.get(9)?,
+            etag: row.get(10)?,
+            last_modified: row.get(11)?,
+        }),
+        None => None,
+    };
+    Ok(Enclosure {
+        id: Some(id),
+        url,
+        mime_type: row.get(2)?,
+        length: row.get(3)?,
+        duration: row.get(4)?,
+        download,
+    })
+}
+
+fn download_from_row(row: &Row) -> Result<Download> {
+    Ok(Download {
+        enclosure: row.get(0)?,
+        url: row.get(1)?,
+        path: PathBuf::from(row.get::<_, String>(2)?),
+        state: DownloadState::parse(&row.get::<_, String>(3)?),
+        downloaded: row.get(4)?,
+        total: row.get(5)?,
+        error: row.get(6)?,
+        etag: row.get(7)?,
+        last_modified: row.get(8)?,
+    })
+}
+
+// Treat each word as a literal term so that punctuation
+// in the query isn't parsed as FTS syntax.
+// The last word is matched as a prefix.
+fn fts_query(query: &str) -> String {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect();
+    if terms.is_empty() {
+        String::new()
+    } else {
+        format!("{}*", terms.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(guid: &str, published_at: Option<i64>, retrieved_at: i64) -> Item {
+        Item {
+            id: None,
+            read: false,
+            starred: false,
+            feed: "https://example.com/feed.xml".to_string(),
+            guid: guid.to_string(),
+            retrieved_at,
+            title: Some(guid.to_string()),
+            url: None,
+            author: None,
+            published_at,
+            description: None,
+            content: None,
+            article: None,
+            enclosures: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn undated_items_are_not_pruned_and_added_again() {
+        let path = std::env::temp_dir().join(format!("rssrs-db-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db = Database::new(&path).unwrap();
+        let feed = "https://example.com/feed.xml";
+
+        assert!(db.add_item(&item("dated", Some(100), 100), "Feed").unwrap());
+        assert!(db.add_item(&item("undated", None, 100), "Feed").unwrap());
+        db.set_item_read(&item("undated", None, 100), true).unwrap();
+
+        // Everything is too old, and more than should be kept
+        assert_eq!(db.prune_items(feed, Some(1000), Some(0)).unwrap(), 1);
+
+        // Fetched again later, still without a date
+        assert!(!db.add_item(&item("undated", None, 2000), "Feed").unwrap());
+        let items = db.get_feed_items(feed).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].guid, "undated");
+        assert!(items[0].read);
+
+        // Dated by when it was first seen
+        assert_eq!(items[0].published_at, Some(100));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
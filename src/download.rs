@@ -0,0 +1,171 @@
+use super::db::{Database, Download, DownloadState, Enclosure};
+use reqwest::{header, StatusCode, Url};
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+
+/*
+ * Enclosures are downloaded one at a time, in the background, to a `.part`
+ * file next to their destination. Downloads interrupted by quitting are
+ * picked up again on the next start, continuing where the `.part` file
+ * left off when the server supports range requests and the file hasn't
+ * changed since (`If-Range`). Otherwise they start over.
+ */
+
+// How often progress is saved and reported
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Download queued enclosures until `rx` is closed. Each enclosure id received
+/// is looked up in the download table, so it has to be queued there first.
+/// Progress is reported with `on_progress`, and so are errors that aren't
+/// about a single download, e.g. when the download table can't be read.
+pub fn run<F>(db: &Database, rx: Receiver<i64>, mut on_progress: F)
+where
+    F: FnMut(Result<Download, String>),
+{
+    let mut runtime = match Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(err) => return on_progress(Err(err.to_string())),
+    };
+    loop {
+        let pending = match db.get_pending_downloads() {
+            Ok(pending) => pending,
+            Err(err) => {
+                on_progress(Err(err.to_string()));
+                Vec::new()
+            }
+        };
+        for mut download in pending {
+            download.state = DownloadState::Downloading;
+            download.error = None;
+            on_progress(Ok(download.clone()));
+            let result = runtime.block_on(fetch(db, &mut download, &mut on_progress));
+            match result {
+                Ok(()) => download.state = DownloadState::Done,
+                Err(err) => {
+                    download.state = DownloadState::Failed;
+                    download.error = Some(err.to_string());
+                }
+            }
+            if let Err(err) = db.update_download(&download) {
+                download.state = DownloadState::Failed;
+                download.error = Some(format!("Couldn't save the download's state: {}", err));
+            }
+            on_progress(Ok(download));
+        }
+
+        // Queued downloads are picked up from the database
+        if rx.recv().is_err() {
+            return;
+        }
+    }
+}
+
+async fn fetch<F>(db: &Database, download: &mut Download, on_progress: &mut F) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut(Result<Download, String>),
+{
+    if let Some(dir) = download.path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let part_path = part_path(&download.path);
+    let existing = match fs::metadata(&part_path) {
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    };
+
+    // Weak etags can't be used with If-Range. Without a validator the part
+    // file could be from a different version of the file, so it isn't resumed.
+    let validator = download
+        .etag
+        .clone()
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| download.last_modified.clone())
+        .filter(|_| existing > 0);
+    let mut request = reqwest::Client::new().get(&download.url);
+    if let Some(validator) = &validator {
+        request = request
+            .header(header::RANGE, format!("bytes={}-", existing))
+            .header(header::IF_RANGE, validator.as_str());
+    }
+    let mut resp = request.send().await?;
+    let mut file = match resp.status() {
+        StatusCode::PARTIAL_CONTENT => {
+            download.downloaded = existing as i64;
+            OpenOptions::new().append(true).open(&part_path)?
+        }
+        // The part file is already complete
+        StatusCode::RANGE_NOT_SATISFIABLE if validator.is_some() => {
+            download.downloaded = existing as i64;
+            download.total = Some(existing as i64);
+            fs::rename(&part_path, &download.path)?;
+            return Ok(());
+        }
+        // The server ignored the range, or the file changed, so start over
+        status if status.is_success() => {
+            download.downloaded = 0;
+            download.etag = header_value(&resp, header::ETAG);
+            download.last_modified = header_value(&resp, header::LAST_MODIFIED);
+            File::create(&part_path)?
+        }
+        status => return Err(format!("HTTP {}", status).into()),
+    };
+    if let Some(len) = resp.content_length() {
+        download.total = Some(download.downloaded + len as i64);
+    }
+    db.update_download(download)?;
+
+    let mut last_progress = Instant::now();
+    while let Some(chunk) = resp.chunk().await? {
+        file.write_all(&chunk)?;
+        download.downloaded += chunk.len() as i64;
+        if last_progress.elapsed() >= PROGRESS_INTERVAL {
+            db.update_download(download)?;
+            on_progress(Ok(download.clone()));
+            last_progress = Instant::now();
+        }
+    }
+    file.flush()?;
+    fs::rename(&part_path, &download.path)?;
+    Ok(())
+}
+
+fn header_value(resp: &reqwest::Response, name: header::HeaderName) -> Option<String> {
+    resp.headers().get(name)?.to_str().ok().map(String::from)
+}
+
+fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    path.with_file_name(name)
+}
+
+/// Where to save an enclosure: `<dir>/<feed title>/<id>-<file name from the url>`.
+/// Episodes of some feeds all have the same file name, so the id keeps
+/// their downloads (and `.part` files) apart.
+pub fn download_path(dir: &Path, feed_title: &str, id: i64, enclosure: &Enclosure) -> PathBuf {
+    let name = Url::parse(&enclosure.url)
+        .ok()
+        .and_then(|url| url.path_segments().and_then(|mut segments| segments.next_back().map(String::from)))
+        .map(|name| sanitize(&name))
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "enclosure".to_string());
+    dir.join(sanitize(feed_title)).join(format!("{}-{}", id, name))
+}
+
+// Make a title or url segment safe to use as a file name
+fn sanitize(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    name.trim().trim_start_matches('.').to_string()
+}
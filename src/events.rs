@@ -1,27 +1,33 @@
+use std::error::Error;
 use std::io;
-use std::sync::{
-    mpsc,
-    atomic::{AtomicBool, Ordering},
-    Arc,
-};
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::thread;
 use std::time::Duration;
 
 use termion::event::Key;
 use termion::input::TermRead;
 
-use super::db::Database;
+use super::db::{Database, Download};
 use super::conf::Config;
-use super::feed::{load_feeds, get_items};
+use super::daemon::Client;
+use super::download;
+use super::feed::{get_article, load_feeds, FeedOptions};
+use super::update::{update_feeds, Progress, Schedule};
+use serde_json::json;
 use tokio::runtime::Runtime;
-use futures::stream::FuturesUnordered;
-use futures::StreamExt;
 
+const DAEMON_POLL_INTERVAL: Duration = Duration::from_secs(2);
 
 pub enum Event<I> {
     Input(I),
-    Updating,
-    Updated,
+    // Sent when an update starts and as each feed finishes
+    Updating(Progress),
+    // Sent when all feeds are done
+    Updated(Progress),
+    // An item's extracted article, or why it couldn't be
+    Article(i64, Result<String, String>),
+    // A download started, progressed or finished, or the downloads failed altogether
+    Download(Result<Download, String>),
 }
 
 /// A small event handler that wrap termion input and update events. Each event
@@ -29,19 +35,21 @@ pub enum Event<I> {
 pub struct Events {
     rx: mpsc::Receiver<Event<Key>>,
     input_handle: thread::JoinHandle<()>,
-    ignore_exit_key: Arc<AtomicBool>,
     update_handle: thread::JoinHandle<()>,
+    // Feeds to refresh now; `None` for all of them
+    refresh_tx: mpsc::Sender<Option<String>>,
+    // Enclosures queued for download
+    download_tx: mpsc::Sender<i64>,
+    tx: mpsc::Sender<Event<Key>>,
 }
 
 
 impl Events {
-    pub fn with_config(config: Config) -> Events {
+    pub fn with_config(config: Config) -> Result<Events, Box<dyn Error>> {
         let (tx, rx) = mpsc::channel();
-        let ignore_exit_key = Arc::new(AtomicBool::new(false));
+        // Quitting is up to the key bindings; this thread ends with the program
         let input_handle = {
             let tx = tx.clone();
-            let ignore_exit_key = ignore_exit_key.clone();
-            let exit_key = Key::Char('q');
             thread::spawn(move || {
                 let stdin = io::stdin();
                 for evt in stdin.keys() {
@@ -50,65 +58,143 @@ impl Events {
                             eprintln!("{}", err);
                             return;
                         }
-                        if !ignore_exit_key.load(Ordering::Relaxed) && key == exit_key {
-                            return;
-                        }
                     }
                 }
             })
         };
 
+        // Also resumes downloads left unfinished last time
+        let (download_tx, download_rx) = mpsc::channel();
+        {
+            let tx = tx.clone();
+            let db = Database::new(&config.db_path)?;
+            thread::spawn(move || {
+                download::run(&db, download_rx, |download| {
+                    let _ = tx.send(Event::Download(download));
+                });
+            });
+        }
+
         let update_interval = Duration::from_secs(config.update_interval);
+        let (refresh_tx, refresh_rx) = mpsc::channel();
         let update_handle = {
+            let tx = tx.clone();
+            let mut runtime = Runtime::new()?;
+            let db = Database::new(&config.db_path)?;
             thread::spawn(move || {
-                let mut runtime = Runtime::new().unwrap();
-                let db = Database::new(&config.db_path);
-                loop {
-                    if tx.send(Event::Updating).is_err() {
-                        break;
+                // Leave fetching to the daemon if one is running
+                if let Ok(client) = Client::connect(&config.socket_path) {
+                    if !follow_daemon(client, &tx, &refresh_rx) {
+                        return;
                     }
-                    let feeds_path = config.feeds_path.clone();
-                    let mut futs: FuturesUnordered<_> = load_feeds(&feeds_path)
-                        .map(|feed| get_items(feed.url)).collect();
-                    runtime.block_on(async {
-                        while let Some(result) = futs.next().await {
-                            match result {
-                                Ok(items) => {
-                                    for item in items {
-                                        db.add_item(&item).unwrap();
-                                    }
-                                    if tx.send(Event::Updated).is_err() {
-                                        break;
-                                    }
-                                },
-                                Err(_) => {
-                                    // TODO
-                                }
-                            }
+                }
+
+                let mut schedule = Schedule::new(update_interval);
+                let mut refresh = Some(None);
+                loop {
+                    // Skip updating while the feeds file is invalid
+                    let feeds = match load_feeds(&config.feeds_path) {
+                        Ok(feeds) => schedule.take_due(feeds, refresh),
+                        Err(_) => Vec::new(),
+                    };
+                    if !feeds.is_empty() {
+                        let mut progress = Progress::new(&feeds);
+                        if tx.send(Event::Updating(progress.clone())).is_err() {
+                            break;
+                        }
+                        update_feeds(&mut runtime, &db, feeds, |update| {
+                            progress.record(&update);
+                            tx.send(Event::Updating(progress.clone())).is_ok()
+                        });
+                        if tx.send(Event::Updated(progress)).is_err() {
+                            break;
                         }
-                    });
-                    thread::sleep(update_interval);
+                    }
+
+                    // Wait for the next feed to be due, or a refresh
+                    refresh = match refresh_rx.recv_timeout(schedule.wait()) {
+                        Ok(feed) => Some(feed),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    };
                 }
             })
         };
-        Events {
+
+        Ok(Events {
             rx,
-            ignore_exit_key,
             input_handle,
             update_handle,
-        }
+            refresh_tx,
+            download_tx,
+            tx,
+        })
     }
 
     pub fn next(&self) -> Result<Event<Key>, mpsc::RecvError> {
         self.rx.recv()
     }
 
-    pub fn disable_exit_key(&mut self) {
-        self.ignore_exit_key.store(true, Ordering::Relaxed);
+    pub fn refresh(&self, feed: Option<String>) -> Result<(), String> {
+        self.refresh_tx
+            .send(feed)
+            .map_err(|_| "Updates have stopped, restart to resume them".to_string())
     }
 
-    pub fn enable_exit_key(&mut self) {
-        self.ignore_exit_key.store(false, Ordering::Relaxed);
+    pub fn queue_download(&self, enclosure: i64) -> Result<(), String> {
+        self.download_tx
+            .send(enclosure)
+            .map_err(|_| "Downloads have stopped, restart to resume them".to_string())
+    }
+
+    // Fetch in the background, sending the result as an `Event::Article`
+    pub fn fetch_article(&self, id: i64, url: String, options: FeedOptions) {
+        let tx = self.tx.clone();
+        thread::spawn(move || {
+            let result = match Runtime::new() {
+                Ok(mut runtime) => runtime.block_on(get_article(&url, &options)).map_err(|err| err.to_string()),
+                Err(err) => Err(err.to_string()),
+            };
+            let _ = tx.send(Event::Article(id, result));
+        });
     }
 }
 
+// Mirror the daemon's update cycles and pass refreshes on to it until it
+// goes away. Returns false once the other end of either channel is gone.
+fn follow_daemon(
+    mut client: Client,
+    tx: &mpsc::Sender<Event<Key>>,
+    refresh_rx: &mpsc::Receiver<Option<String>>,
+) -> bool {
+    let mut cycles = None;
+    while let Ok(status) = client.status() {
+        let finished = match cycles {
+            Some(cycles) => status.cycles > cycles,
+            None => false,
+        };
+        let event = if status.updating {
+            Some(Event::Updating(status.progress))
+        } else if finished {
+            Some(Event::Updated(status.progress))
+        } else {
+            None
+        };
+        if let Some(event) = event {
+            if tx.send(event).is_err() {
+                return false;
+            }
+        }
+        cycles = Some(status.cycles);
+        match refresh_rx.recv_timeout(DAEMON_POLL_INTERVAL) {
+            Ok(feed) => {
+                if client.call("refresh", json!({ "feed": feed })).is_err() {
+                    break;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+    true
+}
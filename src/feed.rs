@@ -1,78 +1,871 @@
 use rss::Channel;
+use atom_syndication::{Feed as AtomFeed, Text, TextType};
 use std::path::Path;
-use std::fs::File;
-use std::io::BufReader;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
 use std::io::prelude::*;
-use chrono::{DateTime, Utc};
+use std::io::SeekFrom;
+use chrono::Utc;
 use rusqlite::Result;
 use html2md::parse_html;
-use super::db::Item;
+use super::date::parse_date;
+use super::readability::extract;
+use super::db::{Enclosure, Item, HttpCache};
+use reqwest::{StatusCode, header};
 use std::error::Error;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cmp::Reverse;
 
 const MAX_AGE: i64 = 60*60*24*182; // about 6 months
 
+#[derive(Clone)]
 pub struct Feed {
     pub url: String,
     pub title: String,
-    pub tags: Vec<String>
+    pub tags: Vec<String>,
+    pub options: FeedOptions,
+}
+
+/// Settings that override the defaults for one feed
+#[derive(Debug, Clone, Default)]
+pub struct FeedOptions {
+    // In seconds
+    pub update_interval: Option<i64>,
+    pub max_age: Option<i64>,
+
+    pub max_items: Option<usize>,
+    pub user_agent: Option<String>,
+    pub disabled: bool,
+
+    // Only settable in feeds.toml
+    pub headers: BTreeMap<String, String>,
+    pub auth: Option<Auth>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Auth {
+    Basic {
+        username: String,
+        password: Option<String>,
+    },
+    Bearer {
+        token: String,
+    },
+}
+
+impl FeedOptions {
+    // Parse e.g. `interval=30m, max_age=30d, max_items=100, user_agent=Foo/1.0, disabled`
+    fn parse(text: &str) -> Result<FeedOptions, String> {
+        let mut options = FeedOptions::default();
+        for option in text.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let mut split = option.splitn(2, '=');
+            let key = split.next().unwrap().trim();
+            let value = split.next().map(str::trim);
+            match (key, value) {
+                ("interval", Some(value)) => options.update_interval = Some(parse_duration(value)?),
+                ("max_age", Some(value)) => options.max_age = Some(parse_duration(value)?),
+                ("max_items", Some(value)) => {
+                    let max_items = value.parse()
+                        .map_err(|_| format!("Invalid max_items `{}`", value))?;
+                    options.max_items = Some(max_items);
+                }
+                ("user_agent", Some(value)) => options.user_agent = Some(value.to_string()),
+                ("disabled", None) => options.disabled = true,
+                _ => return Err(format!("Unknown option `{}`", option)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+// Parse e.g. `90s`, `30m`, `2h`, `7d` or `1w` into seconds,
+// for feed options and `published:` queries alike
+pub fn parse_duration(text: &str) -> Result<i64, String> {
+    let invalid = || format!("Invalid duration `{}`, expected e.g. 30m or 7d", text);
+    let unit = match text.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 60 * 60,
+        Some('d') => 60 * 60 * 24,
+        Some('w') => 60 * 60 * 24 * 7,
+        _ => return Err(invalid()),
+    };
+    match text[..text.len() - 1].parse::<i64>() {
+        Ok(amount) if amount > 0 => amount
+            .checked_mul(unit)
+            .ok_or_else(|| format!("Duration `{}` is too long", text)),
+        _ => Err(invalid()),
+    }
+}
+
+// The largest unit that fits exactly, e.g. 1800 is `30m`
+fn format_duration(secs: i64) -> String {
+    let units = [('w', 60 * 60 * 24 * 7), ('d', 60 * 60 * 24), ('h', 60 * 60), ('m', 60)];
+    for (unit, size) in units.iter() {
+        if secs % size == 0 {
+            return format!("{}{}", secs / size, unit);
+        }
+    }
+    format!("{}s", secs)
+}
+
+// Durations are written as strings like `30m` in feeds.toml
+mod duration {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(secs: &Option<i64>, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        match secs {
+            Some(secs) => serializer.serialize_str(&super::format_duration(*secs)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<i64>, D::Error> where D: Deserializer<'de> {
+        let text = String::deserialize(deserializer)?;
+        super::parse_duration(&text).map(Some).map_err(de::Error::custom)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct FeedsFile {
+    #[serde(default, rename = "feed")]
+    feeds: Vec<FeedEntry>,
+}
+
+// A `[[feed]]` table in feeds.toml
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FeedEntry {
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+
+    #[serde(default, with = "duration", skip_serializing_if = "Option::is_none")]
+    interval: Option<i64>,
+    #[serde(default, with = "duration", skip_serializing_if = "Option::is_none")]
+    max_age: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_items: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_agent: Option<String>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    disabled: bool,
+
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    headers: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auth: Option<Auth>,
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+impl From<FeedEntry> for Feed {
+    fn from(entry: FeedEntry) -> Feed {
+        let title = match entry.title {
+            Some(title) => title,
+            None => entry.url.clone(),
+        };
+        Feed {
+            title,
+            url: entry.url,
+            tags: entry.tags,
+            options: FeedOptions {
+                update_interval: entry.interval,
+                max_age: entry.max_age,
+                max_items: entry.max_items,
+                user_agent: entry.user_agent,
+                disabled: entry.disabled,
+                headers: entry.headers,
+                auth: entry.auth,
+            },
+        }
+    }
+}
+
+impl From<&Feed> for FeedEntry {
+    fn from(feed: &Feed) -> FeedEntry {
+        let options = feed.options.clone();
+        FeedEntry {
+            url: feed.url.clone(),
+            title: Some(feed.title.clone()),
+            tags: feed.tags.clone(),
+            interval: options.update_interval,
+            max_age: options.max_age,
+            max_items: options.max_items,
+            user_agent: options.user_agent,
+            disabled: options.disabled,
+            headers: options.headers,
+            auth: options.auth,
+        }
+    }
+}
+
+fn is_toml(path: &Path) -> bool {
+    path.extension().and_then(|ext| ext.to_str()) == Some("toml")
 }
 
 /*
- * Feeds file: one feed per line in the format:
- * <url> <comma-delimited tags>
+ * Feeds file, either feeds.toml with a `[[feed]]` table per feed,
+ * or feeds.txt with one feed per line in the format:
+ * <url> | <title> | <comma-delimited tags> | <comma-delimited options>
  */
-pub fn load_feeds<P>(path: P) -> impl Iterator<Item=Feed> where P: AsRef<Path> {
-    let file = File::open(&path).unwrap_or_else(|_| {
-        File::create(&path).unwrap();
-        File::open(&path).unwrap()
-    });
-
-    BufReader::new(file).lines().filter_map(Result::ok)
-        .filter(|line| !line.starts_with('#')).map(|line| {
-            let mut split = line.splitn(3, '|');
-            let url = split.next().unwrap().to_string();
-            let title = split.next().unwrap().to_string();
-            let tags = split.next().unwrap_or("").split(',').map(|s| s.to_string()).collect();
-            Feed { url, title, tags }
-        })
-}
-
-
-pub async fn get_items(feed_url: String) -> Result<Vec<Item>, Box<dyn Error>> {
-    let content = reqwest::get(&feed_url)
-        .await?
-        .bytes()
-        .await?;
-    let feed = Channel::read_from(&content[..])?;
+pub fn load_feeds<P>(path: P) -> Result<Vec<Feed>, Box<dyn Error>> where P: AsRef<Path> {
+    let path = path.as_ref();
+    if !path.exists() {
+        File::create(path)?;
+    }
+    let content = fs::read_to_string(path)?;
+    let feeds = if is_toml(path) {
+        parse_feeds_toml(&content)
+    } else {
+        parse_feeds_txt(&content)
+    };
+    feeds.map_err(|err| format!("{}: {}", path.display(), err).into())
+}
+
+fn parse_feeds_toml(content: &str) -> Result<Vec<Feed>, String> {
+    let file: FeedsFile = toml::from_str(content).map_err(|err| err.to_string())?;
+    Ok(file.feeds.into_iter().map(Feed::from).collect())
+}
+
+// Every malformed line is reported, not just the first
+fn parse_feeds_txt(content: &str) -> Result<Vec<Feed>, String> {
+    let mut feeds = Vec::new();
+    let mut errors = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let mut split = line.splitn(4, '|');
+        let url = split.next().unwrap().trim().to_string();
+        let title = match split.next() {
+            Some(title) if !url.is_empty() => title.trim().to_string(),
+            _ => {
+                errors.push(format!("line {}: expected `<url> | <title>`", i + 1));
+                continue;
+            }
+        };
+        let tags = split.next().unwrap_or("").split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+        match FeedOptions::parse(split.next().unwrap_or("")) {
+            Ok(options) => feeds.push(Feed { url, title, tags, options }),
+            Err(err) => errors.push(format!("line {}: {}", i + 1, err)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(feeds)
+    } else {
+        Err(errors.join("; "))
+    }
+}
+
+// Add a feed to the end of the feeds file
+pub fn append_feed<P>(path: P, feed: &Feed) -> Result<(), Box<dyn Error>> where P: AsRef<Path> {
+    let mut file = OpenOptions::new().create(true).read(true).append(true).open(&path)?;
+    if is_toml(path.as_ref()) {
+        let entry = FeedsFile { feeds: vec![feed.into()] };
+        writeln!(file, "\n{}", toml::to_string(&entry)?.trim_end())?;
+    } else {
+        // Don't join the new line onto a last line without a newline
+        let mut last = [b'\n'];
+        if file.metadata()?.len() > 0 {
+            file.seek(SeekFrom::End(-1))?;
+            file.read_exact(&mut last)?;
+        }
+        if last[0] != b'\n' {
+            writeln!(file)?;
+        }
+        let clean = |s: &str| s.replace('|', "/").trim().to_string();
+        let tags: Vec<String> = feed.tags.iter().map(|tag| clean(tag).replace(',', " ")).collect();
+        writeln!(file, "{} | {} | {}", feed.url, clean(&feed.title), tags.join(","))?;
+    }
+    Ok(())
+}
+
+// Write feeds in the feeds.toml format
+pub fn write_feeds_toml<W>(feeds: &[Feed], mut writer: W) -> Result<(), Box<dyn Error>> where W: Write {
+    let file = FeedsFile { feeds: feeds.iter().map(FeedEntry::from).collect() };
+    writer.write_all(toml::to_string(&file)?.as_bytes())?;
+    Ok(())
+}
+
+enum Format {
+    Rss,
+    Atom,
+    Json,
+}
+
+// Use the content type if it's specific enough,
+// otherwise sniff the document itself
+fn detect_format(content_type: Option<&str>, content: &[u8]) -> Format {
+    match content_type {
+        Some("application/feed+json") | Some("application/json") => return Format::Json,
+        Some("application/atom+xml") => return Format::Atom,
+        Some("application/rss+xml") => return Format::Rss,
+        _ => {}
+    }
+
+    // A JSON document; its `version` key is checked when parsing
+    let first = content.iter().find(|b| !b.is_ascii_whitespace());
+    if first == Some(&b'{') {
+        return Format::Json;
+    }
+
+    // Otherwise check the document's root element to tell RSS and Atom apart
+    let text = String::from_utf8_lossy(&content[..usize::min(content.len(), 1024)]);
+    let mut rest = &text[..];
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        // Skip the XML declaration, comments, doctypes, etc
+        if rest.starts_with('?') || rest.starts_with('!') {
+            continue;
+        }
+        let name: String = rest
+            .chars()
+            .take_while(|c| !c.is_whitespace() && *c != '>' && *c != '/')
+            .collect();
+        let local = name.rsplit(':').next().unwrap_or("");
+        return match local {
+            "feed" => Format::Atom,
+            _ => Format::Rss,
+        };
+    }
+    Format::Rss
+}
+
+// Fetch a feed just for its title
+pub async fn get_title(feed_url: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let resp = reqwest::get(feed_url).await?.error_for_status()?;
+    let content_type = header_value(&resp, header::CONTENT_TYPE)
+        .map(|val| val.split(';').next().unwrap_or("").trim().to_lowercase());
+    let content = resp.bytes().await?;
+    let title = match detect_format(content_type.as_deref(), &content) {
+        Format::Rss => Some(Channel::read_from(&content[..])?.title().to_string()),
+        Format::Atom => Some(atom_text(AtomFeed::read_from(&content[..])?.title())),
+        Format::Json => serde_json::from_slice::<JsonFeed>(&content)?.title,
+    };
+    Ok(title.filter(|title| !title.trim().is_empty()))
+}
+
+// Download an item's page and extract its article, with the same
+// user agent, headers and auth as its feed
+pub async fn get_article(url: &str, options: &FeedOptions) -> Result<String, Box<dyn Error>> {
+    let resp = request(url, options).send().await?.error_for_status()?;
+    // Links are relative to where any redirects ended up
+    let base = resp.url().clone();
+    let html = resp.text().await?;
+    extract(html.as_bytes(), &base).ok_or_else(|| "Couldn't find the article".into())
+}
+
+pub enum Fetch {
+    NotModified,
+    Updated {
+        status: u16,
+        items: Vec<Item>,
+        cache: HttpCache,
+    },
+}
+
+fn header_value(resp: &reqwest::Response, name: header::HeaderName) -> Option<String> {
+    resp.headers().get(name)
+        .and_then(|val| val.to_str().ok())
+        .map(Into::into)
+}
+
+// A GET request with a feed's user agent, headers and auth
+fn request(url: &str, options: &FeedOptions) -> reqwest::RequestBuilder {
+    let mut req = reqwest::Client::new().get(url);
+    if let Some(user_agent) = &options.user_agent {
+        req = req.header(header::USER_AGENT, user_agent);
+    }
+    for (name, value) in &options.headers {
+        req = req.header(name.as_str(), value.as_str());
+    }
+    match &options.auth {
+        Some(Auth::Basic { username, password }) => req.basic_auth(username, password.as_ref()),
+        Some(Auth::Bearer { token }) => req.bearer_auth(token),
+        None => req,
+    }
+}
+
+pub async fn get_items(feed_url: String, options: &FeedOptions, cache: HttpCache) -> Result<Fetch, Box<dyn Error>> {
+    // Conditional request, so unchanged feeds aren't downloaded again
+    let mut req = request(&feed_url, options);
+    if let Some(etag) = &cache.etag {
+        req = req.header(header::IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &cache.last_modified {
+        req = req.header(header::IF_MODIFIED_SINCE, last_modified);
+    }
+    let resp = req.send().await?.error_for_status()?;
+    if resp.status() == StatusCode::NOT_MODIFIED {
+        return Ok(Fetch::NotModified);
+    }
+
+    let status = resp.status().as_u16();
+    let cache = HttpCache {
+        etag: header_value(&resp, header::ETAG),
+        last_modified: header_value(&resp, header::LAST_MODIFIED),
+    };
+    let content_type = header_value(&resp, header::CONTENT_TYPE)
+        .map(|val| val.split(';').next().unwrap_or("").trim().to_lowercase());
+    let content = resp.bytes().await?;
+    let mut items = match detect_format(content_type.as_deref(), &content) {
+        Format::Rss => parse_rss(&feed_url, &content)?,
+        Format::Atom => parse_atom(&feed_url, &content)?,
+        Format::Json => parse_json(&feed_url, &content)?,
+    };
+
+    // Identify items without a guid by their content. Items without a
+    // (readable) date are left undated, and dated by when they were first seen
+    // once saved, as a date from this fetch would change with every fetch.
+    for item in &mut items {
+        if item.guid.is_empty() {
+            item.guid = content_hash(item);
+        }
+    }
+
+    // Only save items above a certain age
+    let now = Utc::now().timestamp();
+    let max_age = options.max_age.unwrap_or(MAX_AGE);
+    let mut items: Vec<Item> = items.into_iter().filter(|item| match item.published_at {
+        Some(published) => published > now - max_age,
+        None => true
+    }).collect();
+
+    // and only the newest ones if there's a limit
+    if let Some(max_items) = options.max_items {
+        items.sort_by_key(|item| Reverse(item.published_at.unwrap_or(item.retrieved_at)));
+        items.truncate(max_items);
+    }
+    Ok(Fetch::Updated { status, items, cache })
+}
+
+fn parse_rss(feed_url: &str, content: &[u8]) -> Result<Vec<Item>, Box<dyn Error>> {
+    let feed = Channel::read_from(content)?;
     let now = Utc::now().timestamp();
     let mut items = Vec::new();
     for it in feed.items() {
+        let duration = it.itunes_ext()
+            .and_then(|itunes| itunes.duration())
+            .and_then(parse_itunes_duration);
+        let enclosures = it.enclosure().map(|enclosure| Enclosure {
+            id: None,
+            url: enclosure.url().to_string(),
+            mime_type: Some(enclosure.mime_type().to_string()).filter(|mime_type| !mime_type.is_empty()),
+            length: enclosure.length().trim().parse().ok().filter(|length| *length > 0),
+            duration,
+            download: None,
+        });
         let item = Item {
+            id: None,
             read: false,
             starred: false,
-            feed: feed_url.clone(),
+            feed: feed_url.to_string(),
+            guid: it.guid().map(|guid| guid.value().to_string()).unwrap_or_default(),
             title: it.title().map(Into::into),
             url: it.link().map(Into::into),
+            author: it.author().map(Into::into).or_else(|| {
+                it.dublin_core_ext().and_then(|dc| dc.creators().first().cloned())
+            }),
             retrieved_at: now,
-            published_at: match it.pub_date().map(Into::into) {
-                Some(pub_date) => {
-                    let dt = DateTime::parse_from_rfc2822(pub_date).unwrap();
-                    Some(dt.timestamp())
-                },
-                None => None
-            },
+            published_at: it.pub_date().and_then(parse_date).or_else(|| {
+                it.dublin_core_ext()
+                    .and_then(|dc| dc.dates().iter().find_map(|date| parse_date(date)))
+            }),
             description: match it.description().map(Into::into) {
                 Some(desc) => Some(parse_html(desc)),
                 None => None
             },
+            content: it.content().map(parse_html),
+            article: None,
+            enclosures: enclosures.into_iter().collect(),
+        };
+        items.push(item);
+    }
+    Ok(items)
+}
+
+fn parse_atom(feed_url: &str, content: &[u8]) -> Result<Vec<Item>, Box<dyn Error>> {
+    let feed = AtomFeed::read_from(content)?;
+    let now = Utc::now().timestamp();
+    let mut items = Vec::new();
+    for entry in feed.entries() {
+        // Prefer the alternate link; a link without a `rel` is alternate by default
+        let url = entry.links().iter()
+            .find(|link| link.rel() == "alternate")
+            .or_else(|| entry.links().first())
+            .map(|link| link.href().to_string())
+            .or_else(|| {
+                // Some feeds use the permalink as the entry id
+                let id = entry.id();
+                if id.starts_with("http://") || id.starts_with("https://") {
+                    Some(id.to_string())
+                } else {
+                    None
+                }
+            });
+
+        let content = match entry.content() {
+            Some(content) => content.value().map(|value| match content.content_type() {
+                Some("text") => value.to_string(),
+                _ => parse_html(value),
+            }),
+            None => None
+        };
+
+        let enclosures = entry.links().iter()
+            .filter(|link| link.rel() == "enclosure")
+            .map(|link| Enclosure {
+                id: None,
+                url: link.href().to_string(),
+                mime_type: link.mime_type().map(Into::into),
+                length: link.length().and_then(|length| length.trim().parse().ok()),
+                duration: None,
+                download: None,
+            })
+            .collect();
+
+        let published = entry.published().unwrap_or_else(|| entry.updated());
+        let item = Item {
+            id: None,
+            read: false,
+            starred: false,
+            feed: feed_url.to_string(),
+            guid: entry.id().to_string(),
+            title: Some(atom_text(entry.title())),
+            url,
+            author: join_authors(entry.authors().iter().map(|person| person.name())),
+            retrieved_at: now,
+            published_at: Some(published.timestamp()),
+            description: entry.summary().map(atom_text),
+            content,
+            article: None,
+            enclosures,
         };
+        items.push(item);
+    }
+    Ok(items)
+}
+
+fn atom_text(text: &Text) -> String {
+    match text.r#type {
+        TextType::Text => text.value.clone(),
+        TextType::Html | TextType::Xhtml => parse_html(&text.value),
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonFeed {
+    version: String,
+    title: Option<String>,
+    #[serde(default)]
+    items: Vec<JsonFeedItem>,
+}
 
-        // Only save items above a certain age
-        if let Some(published) = item.published_at {
-            if published > now - MAX_AGE {
-                items.push(item);
+#[derive(Deserialize)]
+struct JsonFeedItem {
+    id: serde_json::Value,
+    url: Option<String>,
+    external_url: Option<String>,
+    title: Option<String>,
+    content_html: Option<String>,
+    content_text: Option<String>,
+    summary: Option<String>,
+    date_published: Option<String>,
+    date_modified: Option<String>,
+
+    // 1.0 has a single `author`, 1.1 has `authors`
+    author: Option<JsonFeedAuthor>,
+    #[serde(default)]
+    authors: Vec<JsonFeedAuthor>,
+    #[serde(default)]
+    attachments: Vec<JsonFeedAttachment>,
+}
+
+#[derive(Deserialize)]
+struct JsonFeedAuthor {
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JsonFeedAttachment {
+    url: String,
+    mime_type: Option<String>,
+    size_in_bytes: Option<f64>,
+    duration_in_seconds: Option<f64>,
+}
+
+fn parse_json(feed_url: &str, content: &[u8]) -> Result<Vec<Item>, Box<dyn Error>> {
+    let feed: JsonFeed = serde_json::from_slice(content)?;
+    if !feed.version.starts_with("https://jsonfeed.org/version/") {
+        return Err(format!("Unsupported JSON Feed version: {}", feed.version).into());
+    }
+
+    let now = Utc::now().timestamp();
+    let mut items = Vec::new();
+    for it in feed.items {
+        // Ids are supposed to be strings but some feeds use numbers
+        let id = match it.id {
+            serde_json::Value::String(id) => id,
+            id => id.to_string(),
+        };
+        let url = it.url.or(it.external_url).or_else(|| {
+            if id.starts_with("http://") || id.starts_with("https://") {
+                Some(id.clone())
+            } else {
+                None
             }
-        }
+        });
+        let authors = it.authors.iter().chain(it.author.iter())
+            .filter_map(|author| author.name.as_deref());
+        let enclosures = it.attachments.into_iter()
+            .map(|attachment| Enclosure {
+                id: None,
+                url: attachment.url,
+                mime_type: attachment.mime_type,
+                length: attachment.size_in_bytes.map(|size| size as i64),
+                duration: attachment.duration_in_seconds.map(|secs| secs as i64),
+                download: None,
+            })
+            .collect();
+
+        let item = Item {
+            id: None,
+            read: false,
+            starred: false,
+            feed: feed_url.to_string(),
+            guid: id,
+            title: it.title,
+            url,
+            author: join_authors(authors),
+            retrieved_at: now,
+            published_at: it.date_published.or(it.date_modified)
+                .and_then(|date| parse_date(&date)),
+            description: it.summary,
+            content: match it.content_html {
+                Some(html) => Some(parse_html(&html)),
+                None => it.content_text,
+            },
+            article: None,
+            enclosures,
+        };
+        items.push(item);
     }
     Ok(items)
 }
+
+// `itunes:duration` is either seconds, or `MM:SS` or `H:MM:SS`
+fn parse_itunes_duration(text: &str) -> Option<i64> {
+    let mut secs = 0;
+    for part in text.trim().split(':') {
+        secs = secs * 60 + part.parse::<i64>().ok()?;
+    }
+    Some(secs)
+}
+
+// Only from fields that don't change when an item is edited,
+// so an edited description doesn't make it a new item
+fn content_hash(item: &Item) -> String {
+    let key = item.url.as_deref().or(item.title.as_deref()).unwrap_or("");
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+fn join_authors<'a, I>(names: I) -> Option<String> where I: Iterator<Item=&'a str> {
+    let names: Vec<&str> = names.filter(|name| !name.is_empty()).collect();
+    if names.is_empty() {
+        None
+    } else {
+        Some(names.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("rssrs-feeds-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn feeds_txt() {
+        let feeds = parse_feeds_txt(
+            "# comment\n\
+             \n\
+             https://hnrss.org/frontpage | Hacker News | tech, news\n\
+             https://example.com/feed|Example\n\
+             https://hnrss.org/newest | HN Newest | tech | interval=10m, max_age=2d, max_items=200, user_agent=Foo/1.0, disabled\n",
+        )
+        .unwrap();
+        assert_eq!(feeds.len(), 3);
+        assert_eq!(feeds[0].url, "https://hnrss.org/frontpage");
+        assert_eq!(feeds[0].title, "Hacker News");
+        assert_eq!(feeds[0].tags, vec!["tech", "news"]);
+        assert_eq!(feeds[1].url, "https://example.com/feed");
+        assert_eq!(feeds[1].title, "Example");
+        assert!(feeds[1].tags.is_empty());
+
+        let options = &feeds[2].options;
+        assert_eq!(options.update_interval, Some(10 * 60));
+        assert_eq!(options.max_age, Some(2 * 24 * 60 * 60));
+        assert_eq!(options.max_items, Some(200));
+        assert_eq!(options.user_agent.as_deref(), Some("Foo/1.0"));
+        assert!(options.disabled);
+    }
+
+    #[test]
+    fn feeds_txt_errors() {
+        let err = parse_feeds_txt(
+            "https://a.com | A\n\
+             no title\n\
+             https://b.com | B | | interval=soon\n\
+             https://c.com | C | | colour=red\n\
+             https://d.com | D | | max_items=lots\n",
+        )
+        .err().unwrap();
+        assert_eq!(
+            err,
+            "line 2: expected `<url> | <title>`; \
+             line 3: Invalid duration `soon`, expected e.g. 30m or 7d; \
+             line 4: Unknown option `colour=red`; \
+             line 5: Invalid max_items `lots`"
+        );
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90s"), Ok(90));
+        assert_eq!(parse_duration("30m"), Ok(30 * 60));
+        assert_eq!(parse_duration("2h"), Ok(2 * 60 * 60));
+        assert_eq!(parse_duration("7d"), Ok(7 * 24 * 60 * 60));
+        assert_eq!(parse_duration("1w"), Ok(7 * 24 * 60 * 60));
+        for invalid in &["", "m", "0m", "-5m", "5", "5y", "1.5h"] {
+            assert!(parse_duration(invalid).is_err(), "{}", invalid);
+        }
+        assert_eq!(
+            parse_duration("99999999999999999w"),
+            Err("Duration `99999999999999999w` is too long".to_string())
+        );
+
+        assert_eq!(format_duration(90), "90s");
+        assert_eq!(format_duration(30 * 60), "30m");
+        assert_eq!(format_duration(14 * 24 * 60 * 60), "2w");
+    }
+
+    #[test]
+    fn feeds_toml() {
+        let feeds = parse_feeds_toml(
+            r#"
+            [[feed]]
+            url = "https://hnrss.org/frontpage"
+            title = "Hacker News"
+            tags = ["tech", "news"]
+
+            [[feed]]
+            url = "https://example.com/private.xml"
+            interval = "10m"
+            max_age = "30d"
+            max_items = 200
+            user_agent = "Mozilla/5.0"
+            disabled = true
+            headers = { X-Api-Key = "secret" }
+            auth = { token = "abc" }
+
+            [[feed]]
+            url = "https://example.com/basic.xml"
+            auth = { username = "me", password = "pw" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(feeds.len(), 3);
+        assert_eq!(feeds[0].title, "Hacker News");
+        assert_eq!(feeds[0].tags, vec!["tech", "news"]);
+
+        let options = &feeds[1].options;
+        assert_eq!(feeds[1].title, "https://example.com/private.xml");
+        assert_eq!(options.update_interval, Some(10 * 60));
+        assert_eq!(options.max_age, Some(30 * 24 * 60 * 60));
+        assert_eq!(options.max_items, Some(200));
+        assert_eq!(options.user_agent.as_deref(), Some("Mozilla/5.0"));
+        assert!(options.disabled);
+        assert_eq!(options.headers.get("X-Api-Key").map(String::as_str), Some("secret"));
+        assert!(matches!(&options.auth, Some(Auth::Bearer { token }) if token == "abc"));
+        assert!(matches!(
+            &feeds[2].options.auth,
+            Some(Auth::Basic { username, password: Some(password) }) if username == "me" && password == "pw"
+        ));
+    }
+
+    #[test]
+    fn feeds_toml_errors() {
+        assert!(parse_feeds_toml("[[feed]]\ntitle = \"No url\"\n").is_err());
+        assert!(parse_feeds_toml("[[feed]]\nurl = \"https://a.com\"\ninterval = \"soon\"\n")
+            .err().unwrap()
+            .contains("Invalid duration `soon`"));
+        assert!(parse_feeds_toml("[[feed]]\nurl = \"https://a.com\"\nintervl = \"1m\"\n")
+            .err().unwrap()
+            .contains("intervl"));
+        assert!(parse_feeds_toml("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn feeds_toml_round_trip() {
+        let feeds = parse_feeds_txt("https://a.com | A | x,y | interval=90m, disabled\n").unwrap();
+        let mut out = Vec::new();
+        write_feeds_toml(&feeds, &mut out).unwrap();
+        let again = parse_feeds_toml(&String::from_utf8(out).unwrap()).unwrap();
+        assert_eq!(again[0].url, "https://a.com");
+        assert_eq!(again[0].title, "A");
+        assert_eq!(again[0].tags, vec!["x", "y"]);
+        assert_eq!(again[0].options.update_interval, Some(90 * 60));
+        assert!(again[0].options.disabled);
+    }
+
+    #[test]
+    fn append_to_feeds_txt() {
+        let path = temp_path("feeds.txt");
+        fs::write(&path, "https://a.com | A").unwrap();
+        let feed = Feed {
+            url: "https://b.com".to_string(),
+            title: "B | b".to_string(),
+            tags: vec!["x,y".to_string()],
+            options: FeedOptions::default(),
+        };
+        append_feed(&path, &feed).unwrap();
+        append_feed(&path, &feed).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "https://a.com | A\nhttps://b.com | B / b | x y\nhttps://b.com | B / b | x y\n"
+        );
+        assert_eq!(load_feeds(&path).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn append_to_feeds_toml() {
+        let path = temp_path("feeds.toml");
+        fs::write(&path, "[[feed]]\nurl = \"https://a.com\"").unwrap();
+        let feed = Feed {
+            url: "https://b.com".to_string(),
+            title: "\"B\"".to_string(),
+            tags: Vec::new(),
+            options: FeedOptions::default(),
+        };
+        append_feed(&path, &feed).unwrap();
+        let feeds = load_feeds(&path).unwrap();
+        assert_eq!(feeds.len(), 2);
+        assert_eq!(feeds[1].title, "\"B\"");
+    }
+}
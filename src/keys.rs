@@ -0,0 +1,519 @@
+use super::conf::KeyBinding;
+use std::collections::HashMap;
+use termion::event::Key;
+
+/*
+ * Key bindings for the item list and reader. Actions are bound to key
+ * sequences in the `[keys]` section of config.toml, e.g.
+ *
+ *   [keys]
+ *   scroll_items_down = ["j", "<Down>"]
+ *   jump_to_first_item = "gg"
+ *   page_items_down = "<C-d>"
+ *
+ * A key is a character, or a named key in angle brackets: <C-x> (ctrl),
+ * <A-x> (alt), <Esc>, <Enter>, <Space>, <Tab>, <S-Tab>, <Backspace>, <Up>,
+ * <Down>, <Left>, <Right>, <PageUp>, <PageDown>, <Home>, <End>, <Del>,
+ * <Ins>, <F1> to <F12>, and <lt> for `<` itself. Configured actions replace
+ * their default keys; `[]` unbinds an action.
+ *
+ * Popups have their own actions, and only look up single keys. So do text
+ * inputs, for keys that aren't typed, and the feeds popup, where typing
+ * filters the feeds. Bindings only conflict when they're used in the same
+ * place.
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Quit,
+    ShowHelp,
+    ScrollItemsDown,
+    ScrollItemsUp,
+    PageItemsDown,
+    PageItemsUp,
+    JumpToFirstItem,
+    JumpToLastItem,
+    ScrollReaderDown,
+    ScrollReaderUp,
+    ToggleFocusReader,
+    ToggleSummary,
+    FetchArticle,
+    DownloadAttachments,
+    ShowLinks,
+    OpenMarked,
+    ToggleSelectedRead,
+    ToggleSelectedStar,
+    ToggleSelectedMark,
+    Clear,
+    Search,
+    JumpToNextResult,
+    JumpToPrevResult,
+    FullTextSearch,
+    Filter,
+    ShowViews,
+    ShowTags,
+    ShowChannels,
+    ShowHealth,
+    ToggleReadFilter,
+    ToggleStarredFilter,
+    ToggleKeywordFilter,
+    Refresh,
+    RefreshFeed,
+    PopupNext,
+    PopupPrevious,
+    PopupToggle,
+    PopupSelect,
+    PopupClose,
+    CopyLink,
+}
+
+// Where an action can be used
+const LIST: u8 = 1;
+const POPUP: u8 = 2;
+
+// Each action's name in config.toml, where it's used, description and
+// default keys, in the order they're listed in the help
+const ACTIONS: &[(Action, &str, u8, &str, &[&str])] = &[
+    (Action::Quit, "quit", LIST | POPUP, "Quit", &["q"]),
+    (Action::ShowHelp, "show_help", LIST | POPUP, "Show these key bindings", &["?"]),
+    (Action::ScrollItemsDown, "scroll_items_down", LIST, "Next item", &["j"]),
+    (Action::ScrollItemsUp, "scroll_items_up", LIST, "Previous item", &["k"]),
+    (Action::PageItemsDown, "page_items_down", LIST, "Jump 5 items down", &["<C-d>"]),
+    (Action::PageItemsUp, "page_items_up", LIST, "Jump 5 items up", &["<C-u>"]),
+    (Action::JumpToFirstItem, "jump_to_first_item", LIST, "First item", &["gg"]),
+    (Action::JumpToLastItem, "jump_to_last_item", LIST, "Last item", &["G"]),
+    (Action::ScrollReaderDown, "scroll_reader_down", LIST, "Scroll the reader down", &["J"]),
+    (Action::ScrollReaderUp, "scroll_reader_up", LIST, "Scroll the reader up", &["K"]),
+    (Action::ToggleFocusReader, "toggle_focus_reader", LIST, "Toggle showing only the reader", &["f"]),
+    (Action::ToggleSummary, "toggle_summary", LIST, "Toggle between the summary and full content", &["c"]),
+    (Action::FetchArticle, "fetch_article", LIST, "Fetch the full article from the item's page", &["a"]),
+    (Action::DownloadAttachments, "download_attachments", LIST, "Download the item's attachments", &["d"]),
+    (Action::ShowLinks, "show_links", LIST | POPUP, "Pick a link to open or copy; or type its number", &["o"]),
+    (Action::OpenMarked, "open_marked", LIST, "Open the marked items in the browser", &["O"]),
+    (Action::ToggleSelectedRead, "toggle_selected_read", LIST, "Toggle read", &["r"]),
+    (Action::ToggleSelectedStar, "toggle_selected_star", LIST, "Toggle starred", &["s"]),
+    (Action::ToggleSelectedMark, "toggle_selected_mark", LIST, "Toggle marked", &["<Space>"]),
+    (Action::Clear, "clear", LIST, "Clear the full-text search, or the marked items", &["<Esc>"]),
+    (Action::Search, "search", LIST, "Highlight items matching a regex", &["/"]),
+    (Action::JumpToNextResult, "jump_to_next_result", LIST, "Next highlighted item", &["n"]),
+    (Action::JumpToPrevResult, "jump_to_prev_result", LIST, "Previous highlighted item", &["N"]),
+    (Action::FullTextSearch, "full_text_search", LIST, "Search all items", &["<C-f>"]),
+    (Action::Filter, "filter", LIST, "Filter items with a query", &["F"]),
+    (Action::ShowViews, "show_views", LIST | POPUP, "Switch to a saved view", &["V"]),
+    (Action::ShowTags, "show_tags", LIST, "Filter by tags", &["T"]),
+    (Action::ShowChannels, "show_channels", LIST, "Filter by feeds", &["C"]),
+    (Action::ShowHealth, "show_health", LIST | POPUP, "Show feed health", &["H"]),
+    (Action::ToggleReadFilter, "toggle_read_filter", LIST, "Cycle through all, unread and read items", &["R"]),
+    (Action::ToggleStarredFilter, "toggle_starred_filter", LIST, "Toggle showing only starred items", &["S"]),
+    (Action::ToggleKeywordFilter, "toggle_keyword_filter", LIST, "Toggle the keyword filter", &["W"]),
+    (Action::Refresh, "refresh", LIST, "Update all feeds now", &["u"]),
+    (Action::RefreshFeed, "refresh_feed", LIST, "Update the selected item's feed now", &["U"]),
+    (Action::PopupNext, "popup_next", POPUP, "Popups: next option", &["j", "<Down>", "<C-n>"]),
+    (Action::PopupPrevious, "popup_previous", POPUP, "Popups: previous option", &["k", "<Up>", "<C-p>"]),
+    (Action::PopupToggle, "popup_toggle", POPUP, "Popups: mark the option", &["<Space>"]),
+    (Action::PopupSelect, "popup_select", POPUP, "Popups: apply or open; inputs: submit", &["<Enter>"]),
+    (Action::PopupClose, "popup_close", POPUP, "Popups: close; inputs: cancel", &["<Esc>"]),
+    (Action::CopyLink, "copy_link", POPUP, "Links popup: copy the link", &["y"]),
+];
+
+pub enum Lookup {
+    Action(Action),
+    // The start of a sequence, waiting for the next key
+    Pending,
+    Unbound,
+}
+
+pub struct Keymap {
+    bindings: Vec<(Vec<Key>, Action)>,
+    pending: Vec<Key>,
+}
+
+impl Keymap {
+    /// The default bindings, replaced by the configured ones. Fails on unknown
+    /// actions, invalid keys, and sequences that are ambiguous where they're
+    /// used: bound to more than one action, or the start of another action's
+    /// sequence.
+    pub fn new(config: &HashMap<String, KeyBinding>) -> Result<Keymap, String> {
+        for name in config.keys() {
+            if !ACTIONS.iter().any(|(_, n, _, _, _)| n == name) {
+                return Err(format!("Unknown action `{}` in [keys]", name));
+            }
+        }
+
+        let mut bindings = Vec::new();
+        for (action, name, context, _, defaults) in ACTIONS {
+            let sequences: Vec<&str> = match config.get(*name) {
+                Some(KeyBinding::One(sequence)) => vec![sequence],
+                Some(KeyBinding::Many(sequences)) => sequences.iter().map(String::as_str).collect(),
+                None => defaults.to_vec(),
+            };
+            for sequence in sequences {
+                let keys = parse_keys(sequence)
+                    .map_err(|err| format!("Invalid keys `{}` for {}: {}", sequence, name, err))?;
+                if *context == POPUP && keys.len() > 1 {
+                    return Err(format!("Invalid keys `{}` for {}: popups only take single keys", sequence, name));
+                }
+                bindings.push((keys, *action));
+            }
+        }
+
+        for (i, (keys, action)) in bindings.iter().enumerate() {
+            for (other_keys, other_action) in &bindings[i + 1..] {
+                let shared = context(*action) & context(*other_action);
+                if action == other_action || shared == 0 {
+                    continue;
+                }
+                // Popups only look up single keys, so a sequence can't get in the way there
+                if shared & LIST == 0 && (keys.len() > 1 || other_keys.len() > 1) {
+                    continue;
+                }
+                if keys == other_keys {
+                    return Err(format!(
+                        "`{}` is bound to both {} and {}",
+                        format_keys(keys),
+                        action_name(*action),
+                        action_name(*other_action)
+                    ));
+                }
+                // The shorter sequence would always win
+                if keys.starts_with(other_keys) || other_keys.starts_with(keys) {
+                    return Err(format!(
+                        "`{}` for {} and `{}` for {} conflict, as one starts with the other",
+                        format_keys(keys),
+                        action_name(*action),
+                        format_keys(other_keys),
+                        action_name(*other_action)
+                    ));
+                }
+            }
+        }
+        Ok(Keymap { bindings, pending: Vec::new() })
+    }
+
+    // Look up a key in the item list, where sequences can be typed
+    pub fn feed(&mut self, key: Key) -> Lookup {
+        self.pending.push(key);
+        let mut prefix = false;
+        for (keys, action) in &self.bindings {
+            if context(*action) & LIST == 0 {
+                continue;
+            }
+            if *keys == self.pending {
+                self.pending.clear();
+                return Lookup::Action(*action);
+            }
+            prefix |= keys.starts_with(&self.pending);
+        }
+        if prefix {
+            return Lookup::Pending;
+        }
+
+        // A key that breaks off a sequence counts on its own
+        let broke_off = self.pending.len() > 1;
+        self.pending.clear();
+        if broke_off {
+            self.feed(key)
+        } else {
+            Lookup::Unbound
+        }
+    }
+
+    // Keys typed so far of an unfinished sequence
+    pub fn pending(&self) -> String {
+        format_keys(&self.pending)
+    }
+
+    // Look up a key in a popup, where only single keys are used
+    pub fn popup_action(&self, key: Key) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(keys, action)| context(*action) & POPUP != 0 && keys.len() == 1 && keys[0] == key)
+            .map(|(_, action)| *action)
+    }
+
+    /// An action's first keys for hints, e.g. `[d]` or `[Enter]`, if it's bound
+    pub fn hint(&self, action: Action) -> Option<String> {
+        let (keys, _) = self.bindings.iter().find(|(_, a)| *a == action)?;
+        let keys = format_keys(keys);
+        // A lone named key reads better without its brackets
+        let named = keys.starts_with('<') && keys.ends_with('>') && keys != "<lt>" && keys.matches('<').count() == 1;
+        if named {
+            Some(format!("[{}]", &keys[1..keys.len() - 1]))
+        } else {
+            Some(format!("[{}]", keys))
+        }
+    }
+
+    /// Each bound action's keys, name and description, for the help
+    pub fn help(&self) -> Vec<(String, &'static str, &'static str)> {
+        ACTIONS
+            .iter()
+            .filter_map(|(action, name, _, description, _)| {
+                let keys: Vec<String> = self
+                    .bindings
+                    .iter()
+                    .filter(|(_, a)| a == action)
+                    .map(|(keys, _)| format_keys(keys))
+                    .collect();
+                if keys.is_empty() {
+                    None
+                } else {
+                    Some((keys.join(" "), *name, *description))
+                }
+            })
+            .collect()
+    }
+}
+
+fn action_name(action: Action) -> &'static str {
+    ACTIONS.iter().find(|(a, _, _, _, _)| *a == action).unwrap().1
+}
+
+fn context(action: Action) -> u8 {
+    ACTIONS.iter().find(|(a, _, _, _, _)| *a == action).unwrap().2
+}
+
+fn parse_keys(text: &str) -> Result<Vec<Key>, String> {
+    let mut keys = Vec::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '<' {
+            keys.push(Key::Char(c));
+            continue;
+        }
+        let mut name = String::new();
+        loop {
+            match chars.next() {
+                Some('>') => break,
+                Some(c) => name.push(c),
+                None => return Err("missing `>`, use <lt> for `<`".to_string()),
+            }
+        }
+        keys.push(parse_key_name(&name).ok_or_else(|| format!("unknown key <{}>", name))?);
+    }
+    if keys.is_empty() {
+        return Err("no keys".to_string());
+    }
+    Ok(keys)
+}
+
+fn parse_key_name(name: &str) -> Option<Key> {
+    // Modifiers, e.g. <C-d> or <A-x>
+    let mut chars = name.chars();
+    if let (Some(modifier), Some('-'), Some(c), None) = (chars.next(), chars.next(), chars.next(), chars.next()) {
+        match modifier.to_ascii_lowercase() {
+            'c' => return Some(Key::Ctrl(c.to_ascii_lowercase())),
+            'a' | 'm' => return Some(Key::Alt(c)),
+            _ => {}
+        }
+    }
+
+    let key = match name.to_lowercase().as_str() {
+        "esc" => Key::Esc,
+        "enter" | "cr" | "return" => Key::Char('\n'),
+        "space" => Key::Char(' '),
+        "tab" => Key::Char('\t'),
+        "s-tab" => Key::BackTab,
+        "bs" | "backspace" => Key::Backspace,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "del" | "delete" => Key::Delete,
+        "ins" | "insert" => Key::Insert,
+        "lt" => Key::Char('<'),
+        name => match name.strip_prefix('f').and_then(|n| n.parse().ok()) {
+            Some(n) if (1..=12).contains(&n) => Key::F(n),
+            _ => return None,
+        },
+    };
+    Some(key)
+}
+
+fn format_keys(keys: &[Key]) -> String {
+    keys.iter()
+        .map(|key| match key {
+            Key::Char(' ') => "<Space>".to_string(),
+            Key::Char('\n') => "<Enter>".to_string(),
+            Key::Char('\t') => "<Tab>".to_string(),
+            Key::Char('<') => "<lt>".to_string(),
+            Key::Char(c) => c.to_string(),
+            Key::Ctrl(c) => format!("<C-{}>", c),
+            Key::Alt(c) => format!("<A-{}>", c),
+            Key::Esc => "<Esc>".to_string(),
+            Key::BackTab => "<S-Tab>".to_string(),
+            Key::Backspace => "<Backspace>".to_string(),
+            Key::Up => "<Up>".to_string(),
+            Key::Down => "<Down>".to_string(),
+            Key::Left => "<Left>".to_string(),
+            Key::Right => "<Right>".to_string(),
+            Key::PageUp => "<PageUp>".to_string(),
+            Key::PageDown => "<PageDown>".to_string(),
+            Key::Home => "<Home>".to_string(),
+            Key::End => "<End>".to_string(),
+            Key::Delete => "<Del>".to_string(),
+            Key::Insert => "<Ins>".to_string(),
+            Key::F(n) => format!("<F{}>", n),
+            _ => "<?>".to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keymap(bindings: &[(&str, &[&str])]) -> Result<Keymap, String> {
+        let config = bindings
+            .iter()
+            .map(|(name, keys)| {
+                let keys = keys.iter().map(|key| key.to_string()).collect();
+                (name.to_string(), KeyBinding::Many(keys))
+            })
+            .collect();
+        Keymap::new(&config)
+    }
+
+    fn feed(keymap: &mut Keymap, keys: &str) -> Option<Action> {
+        let mut last = None;
+        for key in parse_keys(keys).unwrap() {
+            last = match keymap.feed(key) {
+                Lookup::Action(action) => Some(action),
+                Lookup::Pending | Lookup::Unbound => None,
+            };
+        }
+        last
+    }
+
+    #[test]
+    fn key_syntax() {
+        assert_eq!(parse_keys("gg"), Ok(vec![Key::Char('g'), Key::Char('g')]));
+        assert_eq!(parse_keys("<C-d>"), Ok(vec![Key::Ctrl('d')]));
+        assert_eq!(parse_keys("<c-D>"), Ok(vec![Key::Ctrl('d')]));
+        assert_eq!(parse_keys("<A-x>"), Ok(vec![Key::Alt('x')]));
+        assert_eq!(parse_keys("<Space><Enter><Esc>"), Ok(vec![Key::Char(' '), Key::Char('\n'), Key::Esc]));
+        assert_eq!(parse_keys("<S-Tab><PageDown><F12>"), Ok(vec![Key::BackTab, Key::PageDown, Key::F(12)]));
+        assert_eq!(parse_keys("<lt>"), Ok(vec![Key::Char('<')]));
+        assert_eq!(parse_keys("<Foo>"), Err("unknown key <Foo>".to_string()));
+        assert_eq!(parse_keys("<F13>"), Err("unknown key <F13>".to_string()));
+        assert_eq!(parse_keys("<C-d"), Err("missing `>`, use <lt> for `<`".to_string()));
+        assert_eq!(parse_keys(""), Err("no keys".to_string()));
+    }
+
+    #[test]
+    fn format_round_trip() {
+        for keys in &["gg", "<C-d>", "<A-x>", "<Space>", "<Enter>", "<S-Tab>", "<lt>", "<F1>", "Z<Esc>"] {
+            assert_eq!(format_keys(&parse_keys(keys).unwrap()), *keys);
+        }
+    }
+
+    #[test]
+    fn sequences() {
+        let mut keymap = keymap(&[]).unwrap();
+        assert!(matches!(keymap.feed(Key::Char('g')), Lookup::Pending));
+        assert_eq!(keymap.pending(), "g");
+        assert!(matches!(keymap.feed(Key::Char('g')), Lookup::Action(Action::JumpToFirstItem)));
+        assert_eq!(keymap.pending(), "");
+
+        // A key that doesn't continue the sequence counts on its own
+        assert!(matches!(keymap.feed(Key::Char('g')), Lookup::Pending));
+        assert!(matches!(keymap.feed(Key::Char('j')), Lookup::Action(Action::ScrollItemsDown)));
+        assert!(matches!(keymap.feed(Key::Char('1')), Lookup::Unbound));
+    }
+
+    #[test]
+    fn configured_bindings_replace_defaults() {
+        let mut keymap = keymap(&[("quit", &["<C-c>", "ZZ"]), ("scroll_items_down", &["<Down>"]), ("clear", &[])]).unwrap();
+        assert_eq!(feed(&mut keymap, "ZZ"), Some(Action::Quit));
+        assert_eq!(feed(&mut keymap, "<C-c>"), Some(Action::Quit));
+        assert_eq!(feed(&mut keymap, "q"), None);
+        assert_eq!(feed(&mut keymap, "<Down>"), Some(Action::ScrollItemsDown));
+        assert_eq!(feed(&mut keymap, "j"), None);
+        assert_eq!(feed(&mut keymap, "<Esc>"), None);
+        assert_eq!(feed(&mut keymap, "k"), Some(Action::ScrollItemsUp));
+    }
+
+    #[test]
+    fn single_string_bindings() {
+        let config: HashMap<String, KeyBinding> = toml::from_str("quit = \"x\"\nshow_help = [\"?\", \"<F1>\"]\n").unwrap();
+        let mut keymap = Keymap::new(&config).unwrap();
+        assert_eq!(feed(&mut keymap, "x"), Some(Action::Quit));
+        assert_eq!(feed(&mut keymap, "<F1>"), Some(Action::ShowHelp));
+    }
+
+    #[test]
+    fn popups() {
+        let keymap = keymap(&[("quit", &["<C-c>", "ZZ"])]).unwrap();
+        assert_eq!(keymap.popup_action(Key::Char('j')), Some(Action::PopupNext));
+        assert_eq!(keymap.popup_action(Key::Down), Some(Action::PopupNext));
+        assert_eq!(keymap.popup_action(Key::Char('\n')), Some(Action::PopupSelect));
+        assert_eq!(keymap.popup_action(Key::Char('y')), Some(Action::CopyLink));
+        assert_eq!(keymap.popup_action(Key::Ctrl('c')), Some(Action::Quit));
+        assert_eq!(keymap.popup_action(Key::Char('Z')), None);
+
+        // Only actions that work in popups
+        assert_eq!(keymap.popup_action(Key::Char('r')), None);
+        assert_eq!(keymap.popup_action(Key::Char('o')), Some(Action::ShowLinks));
+    }
+
+    #[test]
+    fn conflicts() {
+        assert_eq!(
+            keymap(&[("quit", &["j"])]).err().unwrap(),
+            "`j` is bound to both quit and scroll_items_down"
+        );
+        assert_eq!(
+            keymap(&[("quit", &["g"])]).err().unwrap(),
+            "`g` for quit and `gg` for jump_to_first_item conflict, as one starts with the other"
+        );
+        assert_eq!(
+            keymap(&[("toggle_selected_read", &["gr"]), ("toggle_selected_star", &["grs"])]).err().unwrap(),
+            "`gr` for toggle_selected_read and `grs` for toggle_selected_star conflict, as one starts with the other"
+        );
+
+        // Popup actions conflict with actions that also work in popups, but not the rest
+        assert_eq!(
+            keymap(&[("show_health", &["y"])]).err().unwrap(),
+            "`y` is bound to both show_health and copy_link"
+        );
+        assert!(keymap(&[("copy_link", &["d"])]).is_ok());
+        assert!(keymap(&[("popup_close", &["q"])]).is_err());
+        assert!(keymap(&[("quit", &["gq"]), ("popup_next", &["g"])]).is_ok());
+        assert!(keymap(&[("quit", &["g"]), ("popup_next", &["g"]), ("jump_to_first_item", &[])]).is_err());
+
+        // The same keys for one action aren't a conflict
+        assert!(keymap(&[("quit", &["q", "q"])]).is_ok());
+    }
+
+    #[test]
+    fn invalid_bindings() {
+        assert_eq!(keymap(&[("nope", &["x"])]).err().unwrap(), "Unknown action `nope` in [keys]");
+        assert_eq!(
+            keymap(&[("quit", &["<Foo>"])]).err().unwrap(),
+            "Invalid keys `<Foo>` for quit: unknown key <Foo>"
+        );
+        assert_eq!(
+            keymap(&[("popup_next", &["gj"])]).err().unwrap(),
+            "Invalid keys `gj` for popup_next: popups only take single keys"
+        );
+    }
+
+    #[test]
+    fn hints_and_help() {
+        let keymap = keymap(&[("quit", &["<C-c>", "ZZ"]), ("fetch_article", &[]), ("show_views", &["<lt>"])]).unwrap();
+        assert_eq!(keymap.hint(Action::Quit), Some("[C-c]".to_string()));
+        assert_eq!(keymap.hint(Action::PopupSelect), Some("[Enter]".to_string()));
+        assert_eq!(keymap.hint(Action::JumpToFirstItem), Some("[gg]".to_string()));
+        assert_eq!(keymap.hint(Action::ShowViews), Some("[<lt>]".to_string()));
+        assert_eq!(keymap.hint(Action::FetchArticle), None);
+
+        let help = keymap.help();
+        assert_eq!(help[0], ("<C-c> ZZ".to_string(), "quit", "Quit"));
+        assert!(help.iter().all(|(_, name, _)| *name != "fetch_article"));
+        assert_eq!(help.len(), ACTIONS.len() - 1);
+    }
+}
@@ -0,0 +1,161 @@
+use std::io::{self, Write};
+
+/// Replace the markdown links and images in `text` with numbered references,
+/// e.g. `[a post](https://...)` becomes `a post[1]`, returning the new text
+/// and the links in order. A link that appears twice keeps its first number.
+pub fn extract_links(text: &str) -> (String, Vec<String>) {
+    let mut links = Vec::new();
+    let text = replace_links(text, &mut links);
+    (text, links)
+}
+
+fn replace_links(text: &str, links: &mut Vec<String>) -> String {
+    // Only ASCII is matched, so byte offsets are always char boundaries
+    let bytes = text.as_bytes();
+    let mut out = String::new();
+    let mut last = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'[' => match parse_link(text, i) {
+                Some((label_end, url, end)) => {
+                    let image = i > 0 && bytes[i - 1] == b'!';
+                    out.push_str(&text[last..if image { i - 1 } else { i }]);
+
+                    // Images are often inside links
+                    let label = replace_links(&text[i + 1..label_end], links);
+                    let n = link_number(links, url);
+                    if !image {
+                        out.push_str(&format!("{}[{}]", label, n));
+                    } else if label.trim().is_empty() {
+                        out.push_str(&format!("[image][{}]", n));
+                    } else {
+                        out.push_str(&format!("[image: {}][{}]", label.trim(), n));
+                    }
+                    last = end;
+                    i = end;
+                }
+                None => i += 1,
+            },
+            _ => i += 1,
+        }
+    }
+    out.push_str(&text[last.min(text.len())..]);
+    out
+}
+
+// For a `[` at `open`, find the end of the label, the url,
+// and the end of the whole link, if it is a link
+fn parse_link(text: &str, open: usize) -> Option<(usize, &str, usize)> {
+    let bytes = text.as_bytes();
+    let label_end = find_closing(bytes, open, b'[', b']')?;
+    if bytes.get(label_end + 1) != Some(&b'(') {
+        return None;
+    }
+    let url_end = find_closing(bytes, label_end + 1, b'(', b')')?;
+
+    // The url may be followed by a title, e.g. `(https://... "Title")`
+    let url = text[label_end + 2..url_end].split_whitespace().next()?;
+    let url = url.trim_start_matches('<').trim_end_matches('>');
+    if url.is_empty() {
+        None
+    } else {
+        Some((label_end, url, url_end + 1))
+    }
+}
+
+fn find_closing(bytes: &[u8], open: usize, open_byte: u8, close_byte: u8) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = open;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b if b == open_byte => depth += 1,
+            b if b == close_byte => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+// Numbered from 1
+fn link_number(links: &mut Vec<String>, url: &str) -> usize {
+    match links.iter().position(|link| link == url) {
+        Some(i) => i + 1,
+        None => {
+            links.push(url.to_string());
+            links.len()
+        }
+    }
+}
+
+/// Copy to the clipboard with the OSC 52 escape sequence, which most
+/// terminals support, and which also works over ssh
+pub fn copy_to_clipboard(text: &str) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    write!(stdout, "\x1b]52;c;{}\x07", base64::encode(text))?;
+    stdout.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_links() {
+        let (text, links) = extract_links("See [a post](https://a.com/x) and [another](https://b.com).");
+        assert_eq!(text, "See a post[1] and another[2].");
+        assert_eq!(links, vec!["https://a.com/x", "https://b.com"]);
+    }
+
+    #[test]
+    fn repeated_links_keep_their_number() {
+        let (text, links) = extract_links("[one](https://a.com), [two](https://b.com), [again](https://a.com)");
+        assert_eq!(text, "one[1], two[2], again[1]");
+        assert_eq!(links.len(), 2);
+    }
+
+    #[test]
+    fn titles_and_angle_brackets() {
+        let (text, links) = extract_links(r#"[a](https://a.com "A title") [b](<https://b.com/(1)>)"#);
+        assert_eq!(text, "a[1] b[2]");
+        assert_eq!(links, vec!["https://a.com", "https://b.com/(1)"]);
+    }
+
+    #[test]
+    fn images() {
+        let (text, links) = extract_links("![logo](https://a.com/l.png) ![](https://a.com/m.png)");
+        assert_eq!(text, "[image: logo][1] [image][2]");
+        assert_eq!(links, vec!["https://a.com/l.png", "https://a.com/m.png"]);
+    }
+
+    #[test]
+    fn images_inside_links() {
+        let (text, links) = extract_links("[![logo](https://a.com/l.png)](https://a.com)");
+        assert_eq!(text, "[image: logo][1][2]");
+        assert_eq!(links, vec!["https://a.com/l.png", "https://a.com"]);
+    }
+
+    #[test]
+    fn not_links() {
+        for text in &[r"\[escaped\](https://a.com)", "[brackets] only", "[empty]()", "[unclosed](https://a.com", "(https://a.com)"] {
+            let (replaced, links) = extract_links(text);
+            assert_eq!(&replaced, text);
+            assert!(links.is_empty(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn non_ascii_text() {
+        let (text, links) = extract_links("Café [é](https://a.com/é) — fin");
+        assert_eq!(text, "Café é[1] — fin");
+        assert_eq!(links, vec!["https://a.com/é"]);
+    }
+}
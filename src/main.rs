@@ -1,14 +1,26 @@
 mod db;
+mod cli;
+mod daemon;
+mod date;
+mod download;
+mod migrations;
 mod app;
 mod conf;
 mod feed;
+mod keys;
+mod links;
+mod opml;
+mod query;
+mod readability;
 mod view;
 mod events;
+mod update;
 
 use std::{io, error::Error};
 use self::app::{App, Status, InputMode};
 use self::conf::Config;
 use self::events::{Events, Event};
+use self::keys::{Action, Lookup};
 use termion::raw::IntoRawMode;
 use termion::event::Key;
 use tui::{
@@ -19,7 +31,13 @@ use tui::{
 
 fn main() -> Result<(), Box<dyn Error>> {
     let config = Config::load().expect("Failed to load config");
-    let mut app = App::new(&config.db_path, &config.feeds_path);
+
+    let matches = cli::build().get_matches();
+    if let (command, Some(args)) = matches.subcommand() {
+        return cli::run(&config, command, args);
+    }
+
+    let mut app = App::new(&config)?;
     app.load_items();
 
     let stdout = io::stdout().into_raw_mode()?;
@@ -27,70 +45,212 @@ fn main() -> Result<(), Box<dyn Error>> {
     let mut terminal = Terminal::new(backend)?;
     terminal.clear()?;
 
-    let mut events = Events::with_config(config);
+    let events = Events::with_config(config)?;
 
     terminal.clear()?;
     loop {
-        terminal.draw(|mut f| {
-            view::render_browser(&mut app, &mut f);
+        terminal.draw(|mut f| match app.input_mode {
+            InputMode::Health => view::render_health(&mut app, &mut f),
+            _ => view::render_browser(&mut app, &mut f),
         })?;
 
         match events.next()? {
-            Event::Input(input) => match app.input_mode {
-                InputMode::Normal => match input {
-                    Key::Char('q') => break,
-                    Key::Char('r') => app.toggle_selected_read(),
-                    Key::Char('j') => app.scroll_items_down(),
-                    Key::Char('k') => app.scroll_items_up(),
-                    Key::Ctrl('d') => app.page_items_down(),
-                    Key::Ctrl('u') => app.page_items_up(),
-                    Key::Char('o') => app.open_selected(),
-                    Key::Char('O') => app.open_marked(),
-                    Key::Char('J') => app.scroll_reader_down(),
-                    Key::Char('K') => app.scroll_reader_up(),
-                    Key::Char('n') => app.jump_to_next_result(),
-                    Key::Char('N') => app.jump_to_prev_result(),
-                    Key::Char('f') => app.toggle_focus_reader(),
-                    Key::Char('s') => app.toggle_selected_star(),
-                    Key::Char('R') => app.toggle_read_filter(),
-                    Key::Char('S') => app.toggle_starred_filter(),
-                    Key::Char('/') => {
-                        app.start_search();
-                        events.disable_exit_key();
+            Event::Input(input) => {
+                app.message = None;
+                match app.input_mode {
+                    InputMode::Normal => match app.keymap.feed(input) {
+                        Lookup::Action(action) => match action {
+                            Action::Quit => break,
+                            Action::ShowHelp => app.show_help(),
+                            Action::ScrollItemsDown => app.scroll_items_down(),
+                            Action::ScrollItemsUp => app.scroll_items_up(),
+                            Action::PageItemsDown => app.page_items_down(),
+                            Action::PageItemsUp => app.page_items_up(),
+                            Action::JumpToFirstItem => app.jump_to_first_item(),
+                            Action::JumpToLastItem => app.jump_to_last_item(),
+                            Action::ScrollReaderDown => app.scroll_reader_down(),
+                            Action::ScrollReaderUp => app.scroll_reader_up(),
+                            Action::ToggleFocusReader => app.toggle_focus_reader(),
+                            Action::ToggleSummary => app.toggle_summary(),
+                            Action::FetchArticle => if let Some((id, url, options)) = app.start_article_fetch() {
+                                events.fetch_article(id, url, options);
+                            },
+                            Action::DownloadAttachments => for id in app.queue_downloads() {
+                                if let Err(err) = events.queue_download(id) {
+                                    app.message = Some(err);
+                                }
+                            },
+                            Action::ShowLinks => app.show_links(),
+                            Action::OpenMarked => app.open_marked(),
+                            Action::ToggleSelectedRead => app.toggle_selected_read(),
+                            Action::ToggleSelectedStar => app.toggle_selected_star(),
+                            Action::ToggleSelectedMark => app.toggle_selected_mark(),
+                            Action::Clear => if app.fts_query.is_some() {
+                                app.clear_full_text_search();
+                            } else {
+                                app.clear_marked();
+                            },
+                            Action::Search => app.start_search(),
+                            Action::JumpToNextResult => app.jump_to_next_result(),
+                            Action::JumpToPrevResult => app.jump_to_prev_result(),
+                            Action::FullTextSearch => app.start_full_text_search(),
+                            Action::Filter => app.start_query(),
+                            Action::ShowViews => app.show_views(),
+                            Action::ShowTags => app.show_tag_selection(),
+                            Action::ShowChannels => app.show_channel_selection(),
+                            Action::ShowHealth => app.show_health(),
+                            Action::ToggleReadFilter => app.toggle_read_filter(),
+                            Action::ToggleStarredFilter => app.toggle_starred_filter(),
+                            Action::ToggleKeywordFilter => app.toggle_keyword_filter(),
+                            Action::Refresh => if let Err(err) = events.refresh(None) {
+                                app.message = Some(err);
+                            },
+                            Action::RefreshFeed => if let Some(feed) = app.selected_feed() {
+                                if let Err(err) = events.refresh(Some(feed)) {
+                                    app.message = Some(err);
+                                }
+                            },
+                            // Only looked up in popups
+                            Action::PopupNext
+                            | Action::PopupPrevious
+                            | Action::PopupToggle
+                            | Action::PopupSelect
+                            | Action::PopupClose
+                            | Action::CopyLink => {}
+                        },
+                        // Jump straight to a link by its number
+                        Lookup::Unbound => if let Key::Char(c) = input {
+                            if c.is_ascii_digit() {
+                                app.show_links();
+                                if let InputMode::Links = app.input_mode {
+                                    app.push_link_number(c);
+                                }
+                            }
+                        },
+                        Lookup::Pending => {}
                     },
-                    Key::Esc => app.clear_marked(),
-                    Key::Char(' ') => app.toggle_selected_mark(),
-                    _ => {}
-                },
-                InputMode::Search => match input {
-                    Key::Char('\n') => {
-                        let search_query: String = app.search_input_raw.drain(..).collect();
-                        let search_query = app.build_query(&search_query);
-                        app.execute_search(&search_query);
-                        app.search_query = Some(search_query);
-                        app.end_search();
-                    }
-                    Key::Char(c) => {
-                        app.search_input_raw.push(c);
-                        app.search_input = Some(app.build_query(&app.search_input_raw));
-                    }
-                    Key::Backspace => {
-                        app.search_input_raw.pop();
-                        app.search_input = Some(app.build_query(&app.search_input_raw));
-                    }
-                    Key::Esc => {
-                        app.end_search();
-                        events.enable_exit_key();
+                    // Typed text goes to the input, other keys are looked up
+                    InputMode::Search => match input {
+                        Key::Char(c) if !c.is_control() => {
+                            app.search_input_raw.push(c);
+                            app.search_input = Some(app.build_query(&app.search_input_raw));
+                        }
+                        Key::Backspace => {
+                            app.search_input_raw.pop();
+                            app.search_input = Some(app.build_query(&app.search_input_raw));
+                        }
+                        _ => match app.keymap.popup_action(input) {
+                            Some(Action::PopupSelect) => {
+                                let search_query: String = app.search_input_raw.drain(..).collect();
+                                let search_query = app.build_query(&search_query);
+                                app.execute_search(&search_query);
+                                app.search_query = Some(search_query);
+                                app.end_search();
+                            }
+                            Some(Action::PopupClose) => app.end_search(),
+                            _ => {}
+                        },
+                    },
+                    InputMode::FullTextSearch => match input {
+                        Key::Char(c) if !c.is_control() => app.search_input_raw.push(c),
+                        Key::Backspace => {
+                            app.search_input_raw.pop();
+                        }
+                        _ => match app.keymap.popup_action(input) {
+                            Some(Action::PopupSelect) => {
+                                let query: String = app.search_input_raw.drain(..).collect();
+                                app.execute_full_text_search(&query);
+                                app.end_search();
+                            }
+                            Some(Action::PopupClose) => app.end_search(),
+                            _ => {}
+                        },
+                    },
+                    InputMode::Query => match input {
+                        Key::Char(c) if !c.is_control() => {
+                            app.query_input.push(c);
+                            app.update_query_input();
+                        }
+                        Key::Backspace => {
+                            app.query_input.pop();
+                            app.update_query_input();
+                        }
+                        _ => match app.keymap.popup_action(input) {
+                            Some(Action::PopupSelect) => app.apply_query(),
+                            Some(Action::PopupClose) => app.cancel_query(),
+                            _ => {}
+                        },
+                    },
+                    InputMode::Views => match app.keymap.popup_action(input) {
+                        Some(Action::Quit) => break,
+                        Some(Action::PopupNext) => app.selector.next(),
+                        Some(Action::PopupPrevious) => app.selector.previous(),
+                        Some(Action::PopupSelect) => app.apply_view(),
+                        Some(Action::PopupClose) | Some(Action::ShowViews) => app.cancel_selection(),
+                        _ => {}
+                    },
+                    InputMode::Links => match input {
+                        Key::Char(c) if c.is_ascii_digit() => app.push_link_number(c),
+                        Key::Backspace => app.pop_link_number(),
+                        _ => match app.keymap.popup_action(input) {
+                            Some(Action::Quit) => break,
+                            Some(Action::PopupNext) => app.selector.next(),
+                            Some(Action::PopupPrevious) => app.selector.previous(),
+                            Some(Action::PopupSelect) => app.open_link(),
+                            Some(Action::CopyLink) => app.copy_link(),
+                            Some(Action::PopupClose) | Some(Action::ShowLinks) => app.cancel_selection(),
+                            _ => {}
+                        },
+                    },
+                    InputMode::Health => match app.keymap.popup_action(input) {
+                        Some(Action::Quit) => break,
+                        Some(Action::PopupNext) => app.health.next(),
+                        Some(Action::PopupPrevious) => app.health.previous(),
+                        Some(Action::PopupClose) | Some(Action::ShowHealth) => app.hide_health(),
+                        _ => {}
+                    },
+                    InputMode::Help => match app.keymap.popup_action(input) {
+                        Some(Action::Quit) => break,
+                        Some(Action::PopupNext) => app.help.next(),
+                        Some(Action::PopupPrevious) => app.help.previous(),
+                        Some(Action::PopupClose) | Some(Action::ShowHelp) => app.hide_help(),
+                        _ => {}
+                    },
+                    InputMode::Tags => match app.keymap.popup_action(input) {
+                        Some(Action::Quit) => break,
+                        Some(Action::PopupNext) => app.selector.next(),
+                        Some(Action::PopupPrevious) => app.selector.previous(),
+                        Some(Action::PopupToggle) => app.selector.toggle_selected(),
+                        Some(Action::PopupSelect) => app.apply_tag_selection(),
+                        Some(Action::PopupClose) => app.cancel_selection(),
+                        _ => {}
+                    },
+                    // Printable keys filter the feeds
+                    InputMode::Channels => match input {
+                        Key::Char(c) if !c.is_whitespace() && !c.is_control() => app.selector.push_query(c),
+                        Key::Backspace => app.selector.pop_query(),
+                        _ => match app.keymap.popup_action(input) {
+                            Some(Action::PopupNext) => app.selector.next(),
+                            Some(Action::PopupPrevious) => app.selector.previous(),
+                            Some(Action::PopupToggle) => app.selector.toggle_selected(),
+                            Some(Action::PopupSelect) => app.apply_channel_selection(),
+                            Some(Action::PopupClose) => app.cancel_selection(),
+                            _ => {}
+                        },
                     }
-                    _ => {}
                 }
-            },
-            Event::Updating => {
-                app.status = Status::Updating;
             }
-            Event::Updated => {
-                app.status = Status::Idle;
+            Event::Updating(progress) => {
+                app.status = Status::Updating(progress);
+                app.load_new_items();
+            }
+            Event::Article(id, result) => app.set_article(id, result),
+            Event::Download(Ok(download)) => app.update_download(download),
+            Event::Download(Err(err)) => app.message = Some(format!("Downloads failed: {}", err)),
+            Event::Updated(progress) => {
+                app.status = Status::Updated(progress);
                 app.load_new_items();
+                app.refresh_view_counts();
             }
         }
     }
@@ -0,0 +1,202 @@
+use rusqlite::{params, Connection, Result};
+use std::error::Error;
+use std::fs::copy;
+use std::path::Path;
+
+type Migration = fn(&Connection) -> Result<()>;
+
+// Each migration brings the schema from version `i` to version `i + 1`.
+// Only ever append to this list; released migrations must not change.
+const MIGRATIONS: &[Migration] = &[
+    create_items,
+    create_http_cache,
+    create_feed_status,
+    create_item_fts,
+    trim_feed_urls,
+    add_item_content,
+    add_item_article,
+    create_enclosures,
+    add_download_validators,
+];
+
+// Prefix for guids of items carried over from url-keyed databases,
+// which are replaced with the real guid the next time the item is fetched
+pub const LEGACY_GUID_PREFIX: &str = "legacy:";
+
+/// Bring the database up to the latest schema version,
+/// backing up the existing file first if anything needs to change.
+pub fn migrate<P>(conn: &mut Connection, path: P) -> Result<(), Box<dyn Error>>
+where
+    P: AsRef<Path>,
+{
+    let version: usize = conn.query_row("PRAGMA user_version", params![], |row| {
+        row.get::<_, i64>(0).map(|v| v as usize)
+    })?;
+    if version > MIGRATIONS.len() {
+        return Err(format!(
+            "Database schema version {} is newer than this version of rssrs supports ({})",
+            version,
+            MIGRATIONS.len()
+        )
+        .into());
+    }
+    if version == MIGRATIONS.len() {
+        return Ok(());
+    }
+
+    // Nothing to back up for a fresh database
+    let has_tables: bool = conn.query_row(
+        "SELECT count(*) > 0 FROM sqlite_master WHERE type == 'table'",
+        params![],
+        |row| row.get(0),
+    )?;
+    if has_tables {
+        let path = path.as_ref();
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(format!(".v{}.bak", version));
+        copy(path, backup)?;
+    }
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        migration(&tx)?;
+        tx.execute_batch(&format!("PRAGMA user_version = {}", i + 1))?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+const ITEM_TABLE: &str = "CREATE TABLE item (
+                      id              INTEGER PRIMARY KEY,
+                      feed            TEXT NOT NULL,
+                      guid            TEXT NOT NULL,
+                      url             TEXT,
+                      read            INTEGER DEFAULT 0,
+                      starred         INTEGER DEFAULT 0,
+                      title           TEXT,
+                      published_at    INTEGER,
+                      retrieved_at    INTEGER,
+                      description     TEXT,
+                      author          TEXT,
+                      UNIQUE(feed, guid)
+                      )";
+
+fn table_has_column(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+    conn.query_row(
+        "SELECT count(*) > 0 FROM pragma_table_info(?) WHERE name == ?",
+        params![table, column],
+        |row| row.get(0),
+    )
+}
+
+// Databases from before versioning either have no tables yet,
+// an item table keyed by (feed, guid), or one keyed by url.
+fn create_items(conn: &Connection) -> Result<()> {
+    if !table_has_column(conn, "item", "url")? {
+        return conn.execute_batch(ITEM_TABLE);
+    }
+    if table_has_column(conn, "item", "guid")? {
+        return Ok(());
+    }
+
+    // Rebuild a url-keyed item table with (feed, guid) identity
+    if !table_has_column(conn, "item", "author")? {
+        conn.execute("ALTER TABLE item ADD COLUMN author TEXT", params![])?;
+    }
+    conn.execute_batch(&format!(
+        "ALTER TABLE item RENAME TO legacy_item;
+         {};
+         INSERT OR IGNORE INTO item (feed, guid, url, read, starred, title, published_at, retrieved_at, description, author)
+            SELECT coalesce(feed, ''), '{}' || coalesce(url, title, ''), url, read, starred, title, published_at, retrieved_at, description, author
+            FROM legacy_item;
+         DROP TABLE legacy_item;",
+        ITEM_TABLE, LEGACY_GUID_PREFIX
+    ))
+}
+
+fn create_http_cache(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE http_cache (
+                      feed            TEXT PRIMARY KEY,
+                      etag            TEXT,
+                      last_modified   TEXT
+                      )",
+    )
+}
+
+fn create_feed_status(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE feed_status (
+                      feed            TEXT PRIMARY KEY,
+                      last_success    INTEGER,
+                      last_error      TEXT,
+                      http_status     INTEGER,
+                      failures        INTEGER DEFAULT 0
+                      )",
+    )
+}
+
+// Full-text index of items, sharing rowids with the item table.
+// Feed titles aren't known here and are filled in by `Database::sync_feed_titles`.
+fn create_item_fts(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE VIRTUAL TABLE item_fts USING fts5(title, description, feed_title);
+         INSERT INTO item_fts (rowid, title, description, feed_title)
+            SELECT id, coalesce(title, ''), coalesce(description, ''), '' FROM item;",
+    )
+}
+
+// feeds.txt urls used to keep the whitespace before the `|`, and were stored with it.
+// Rows that would collide with a trimmed one are stale duplicates and dropped.
+fn trim_feed_urls(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "UPDATE OR IGNORE item SET feed = trim(feed);
+         DELETE FROM item_fts WHERE rowid IN (SELECT id FROM item WHERE feed != trim(feed));
+         DELETE FROM item WHERE feed != trim(feed);
+         UPDATE OR IGNORE http_cache SET feed = trim(feed);
+         DELETE FROM http_cache WHERE feed != trim(feed);
+         UPDATE OR IGNORE feed_status SET feed = trim(feed);
+         DELETE FROM feed_status WHERE feed != trim(feed);",
+    )
+}
+
+// Full articles, e.g. from `content:encoded`, kept apart from the summary in `description`
+fn add_item_content(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE item ADD COLUMN content TEXT")
+}
+
+// Articles extracted from items' pages, see `readability`
+fn add_item_article(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE item ADD COLUMN article TEXT")
+}
+
+fn create_enclosures(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE enclosure (
+                      id              INTEGER PRIMARY KEY,
+                      item            INTEGER NOT NULL,
+                      url             TEXT NOT NULL,
+                      mime_type       TEXT,
+                      length          INTEGER,
+                      duration        INTEGER,
+                      UNIQUE(item, url)
+                      );
+         CREATE TABLE download (
+                      enclosure       INTEGER PRIMARY KEY,
+                      path            TEXT NOT NULL,
+                      state           TEXT NOT NULL,
+                      downloaded      INTEGER DEFAULT 0,
+                      total           INTEGER,
+                      error           TEXT
+                      );",
+    )
+}
+
+// Validators of the file being downloaded, so a `.part` file is only resumed
+// when the file hasn't changed since
+fn add_download_validators(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE download ADD COLUMN etag TEXT;
+         ALTER TABLE download ADD COLUMN last_modified TEXT;",
+    )
+}
@@ -0,0 +1,228 @@
+use super::feed::{append_feed, load_feeds, Feed, FeedOptions};
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+use std::error::Error;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::Path;
+
+/*
+ * Read the feeds from an OPML document. Outlines without an `xmlUrl`
+ * are folders, and each feed is tagged with the folders it's nested in
+ * along with its own `category` values.
+ */
+pub fn read_opml<R>(reader: R) -> Result<Vec<Feed>, Box<dyn Error>>
+where
+    R: BufRead,
+{
+    let mut reader = Reader::from_reader(reader);
+    reader.trim_text(true);
+
+    let mut feeds = Vec::new();
+    let mut folders: Vec<Option<String>> = Vec::new();
+    let mut buf = Vec::new();
+    loop {
+        let event = reader.read_event(&mut buf)?;
+        match event {
+            Event::Start(ref e) | Event::Empty(ref e) if e.name() == b"outline" => {
+                let mut url = None;
+                let mut title = None;
+                let mut text = None;
+                let mut categories = Vec::new();
+                for attr in e.attributes() {
+                    let attr = attr?;
+                    // Trimmed, as the feeds file is written with spaces around `|`s
+                    let value = attr.unescape_and_decode_value(&reader)?.trim().to_string();
+                    match attr.key {
+                        _ if value.is_empty() => {}
+                        b"xmlUrl" => url = Some(value),
+                        b"title" => title = Some(value),
+                        b"text" => text = Some(value),
+                        b"category" => {
+                            categories = value
+                                .split(&[',', '/'][..])
+                                .map(|s| s.trim().to_string())
+                                .filter(|s| !s.is_empty())
+                                .collect()
+                        }
+                        _ => {}
+                    }
+                }
+                let title = title.or(text);
+
+                let folder = match url {
+                    Some(url) => {
+                        let mut tags: Vec<String> = folders.iter().flatten().cloned().collect();
+                        for category in categories {
+                            if !tags.contains(&category) {
+                                tags.push(category);
+                            }
+                        }
+                        feeds.push(Feed {
+                            title: title.unwrap_or_else(|| url.clone()),
+                            url,
+                            tags,
+                            options: FeedOptions::default(),
+                        });
+                        None
+                    }
+                    None => title,
+                };
+
+                // Only non-empty outlines have children
+                if let Event::Start(_) = event {
+                    folders.push(folder);
+                }
+            }
+            Event::End(ref e) if e.name() == b"outline" => {
+                folders.pop();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(feeds)
+}
+
+pub fn write_opml<W>(feeds: &[Feed], writer: W) -> Result<(), Box<dyn Error>>
+where
+    W: Write,
+{
+    let mut writer = Writer::new_with_indent(writer, b' ', 2);
+    writer.write_event(Event::Decl(BytesDecl::new(b"1.0", Some(b"UTF-8"), None)))?;
+
+    let mut opml = BytesStart::borrowed_name(b"opml");
+    opml.push_attribute(("version", "2.0"));
+    writer.write_event(Event::Start(opml))?;
+
+    writer.write_event(Event::Start(BytesStart::borrowed_name(b"head")))?;
+    writer.write_event(Event::Start(BytesStart::borrowed_name(b"title")))?;
+    writer.write_event(Event::Text(BytesText::from_plain_str("rssrs feeds")))?;
+    writer.write_event(Event::End(BytesEnd::borrowed(b"title")))?;
+    writer.write_event(Event::End(BytesEnd::borrowed(b"head")))?;
+
+    writer.write_event(Event::Start(BytesStart::borrowed_name(b"body")))?;
+    for feed in feeds {
+        let tags = feed.tags.join(",");
+        let mut outline = BytesStart::borrowed_name(b"outline");
+        outline.push_attribute(("type", "rss"));
+        outline.push_attribute(("text", feed.title.as_str()));
+        outline.push_attribute(("title", feed.title.as_str()));
+        outline.push_attribute(("xmlUrl", feed.url.as_str()));
+        if !tags.is_empty() {
+            outline.push_attribute(("category", tags.as_str()));
+        }
+        writer.write_event(Event::Empty(outline))?;
+    }
+    writer.write_event(Event::End(BytesEnd::borrowed(b"body")))?;
+    writer.write_event(Event::End(BytesEnd::borrowed(b"opml")))?;
+    writer.into_inner().write_all(b"\n")?;
+    Ok(())
+}
+
+/// Append the feeds from an OPML file to the feeds file,
+/// skipping ones that are already there. Returns how many were added.
+pub fn import_opml<P, Q>(opml_path: P, feeds_path: Q) -> Result<usize, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let feeds = read_opml(BufReader::new(File::open(opml_path)?))?;
+    let mut existing: Vec<String> = load_feeds(&feeds_path)?
+        .into_iter()
+        .map(|feed| feed.url)
+        .collect();
+
+    let mut added = 0;
+    for feed in feeds {
+        if existing.contains(&feed.url) {
+            continue;
+        }
+        append_feed(&feeds_path, &feed)?;
+        existing.push(feed.url);
+        added += 1;
+    }
+    Ok(added)
+}
+
+pub fn export_opml<P, W>(feeds_path: P, writer: W) -> Result<(), Box<dyn Error>>
+where
+    P: AsRef<Path>,
+    W: Write,
+{
+    write_opml(&load_feeds(feeds_path)?, writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const OPML: &str = r#"<?xml version="1.0"?>
+<opml version="1.0">
+  <head><title>Subscriptions</title></head>
+  <body>
+    <outline text="Tech">
+      <outline text="News">
+        <outline text="HN" xmlUrl=" https://news.ycombinator.com/rss " category="links, reading"/>
+      </outline>
+      <outline title="Lobsters" text="lobste.rs" xmlUrl="https://lobste.rs/rss"></outline>
+    </outline>
+    <outline text="" xmlUrl="https://example.com/feed.xml"/>
+  </body>
+</opml>"#;
+
+    #[test]
+    fn read_nested_outlines() {
+        let feeds = read_opml(OPML.as_bytes()).unwrap();
+        let feeds: Vec<_> = feeds.iter().map(|f| (f.url.as_str(), f.title.as_str(), f.tags.clone())).collect();
+        assert_eq!(
+            feeds,
+            vec![
+                ("https://news.ycombinator.com/rss", "HN", vec!["Tech".to_string(), "News".into(), "links".into(), "reading".into()]),
+                ("https://lobste.rs/rss", "Lobsters", vec!["Tech".to_string()]),
+                ("https://example.com/feed.xml", "https://example.com/feed.xml", vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let feeds = read_opml(OPML.as_bytes()).unwrap();
+        let mut out = Vec::new();
+        write_opml(&feeds, &mut out).unwrap();
+        let again = read_opml(&out[..]).unwrap();
+        assert_eq!(again.len(), feeds.len());
+        for (feed, other) in feeds.iter().zip(&again) {
+            assert_eq!(feed.url, other.url);
+            assert_eq!(feed.title, other.title);
+            assert_eq!(feed.tags, other.tags);
+        }
+    }
+
+    #[test]
+    fn invalid_xml() {
+        assert!(read_opml(&b"<opml><body><outline xmlUrl=\"x></body>"[..]).is_err());
+    }
+
+    #[test]
+    fn import_skips_existing_feeds() {
+        let dir = std::env::temp_dir().join(format!("rssrs-opml-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let opml_path = dir.join("feeds.opml");
+        let feeds_path = dir.join("feeds.txt");
+        fs::write(&opml_path, OPML).unwrap();
+        fs::write(&feeds_path, "https://lobste.rs/rss | Lobsters").unwrap();
+
+        assert_eq!(import_opml(&opml_path, &feeds_path).unwrap(), 2);
+        assert_eq!(import_opml(&opml_path, &feeds_path).unwrap(), 0);
+        let feeds = load_feeds(&feeds_path).unwrap();
+        assert_eq!(feeds.len(), 3);
+        assert_eq!(feeds[1].url, "https://news.ycombinator.com/rss");
+        assert_eq!(feeds[1].title, "HN");
+        assert_eq!(feeds[1].tags, vec!["Tech", "News", "links", "reading"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,570 @@
+/*
+ * A small query language for filtering items, e.g.
+ *
+ *   tag:tech and not feed:"Hacker News" and unread and published:<7d and title:/rust/i
+ *
+ * Terms are combined with `and` (also implied between terms), `or`, `not`
+ * and parentheses. Supported terms:
+ *
+ *   read, unread, starred, unstarred
+ *   tag:<tag>               feed has the tag
+ *   feed:<title or url>     item is from the feed
+ *   title:<text>            title contains the text
+ *   desc:<text>             summary or full content contains the text
+ *   author:<text>           author contains the text
+ *   published:<7d           published less than 7 days ago (s, m, h, d, w)
+ *   published:>2021-01-01   published after the date (`<` for before)
+ *
+ * Text can be a bare word, a "quoted string", or a /regex/ with an optional `i` flag.
+ * Bare terms without a field match the title.
+ */
+use super::db::Item;
+use super::feed::{parse_duration, Feed};
+use chrono::{NaiveDate, Utc};
+use regex::{Regex, RegexBuilder};
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub enum Matcher {
+    Text(String),
+    Regex(Regex),
+}
+
+impl Matcher {
+    // Case-insensitive substring match
+    fn contains(&self, text: &str) -> bool {
+        match self {
+            Matcher::Text(needle) => text.to_lowercase().contains(needle),
+            Matcher::Regex(re) => re.is_match(text),
+        }
+    }
+
+    // Case-insensitive exact match
+    fn equals(&self, text: &str) -> bool {
+        match self {
+            Matcher::Text(needle) => text.to_lowercase() == *needle,
+            Matcher::Regex(re) => re.is_match(text),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Bound {
+    Age(i64),
+    Time(i64),
+}
+
+impl Bound {
+    fn timestamp(&self, now: i64) -> i64 {
+        match self {
+            Bound::Age(secs) => now - secs,
+            Bound::Time(ts) => *ts,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Read,
+    Starred,
+    Tag(Matcher),
+    Feed(Matcher),
+    Title(Matcher),
+    Description(Matcher),
+    Author(Matcher),
+    PublishedAfter(Bound),
+    PublishedBefore(Bound),
+}
+
+impl Expr {
+    pub fn and(self, other: Expr) -> Expr {
+        Expr::And(Box::new(self), Box::new(other))
+    }
+
+    pub fn or(self, other: Expr) -> Expr {
+        Expr::Or(Box::new(self), Box::new(other))
+    }
+
+    pub fn not(self) -> Expr {
+        Expr::Not(Box::new(self))
+    }
+
+    pub fn matches(&self, item: &Item, feed: &Feed) -> bool {
+        self.eval(item, feed, Utc::now().timestamp())
+    }
+
+    fn eval(&self, item: &Item, feed: &Feed, now: i64) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(item, feed, now) && b.eval(item, feed, now),
+            Expr::Or(a, b) => a.eval(item, feed, now) || b.eval(item, feed, now),
+            Expr::Not(a) => !a.eval(item, feed, now),
+            Expr::Read => item.read,
+            Expr::Starred => item.starred,
+            Expr::Tag(m) => feed.tags.iter().any(|tag| m.equals(tag)),
+            Expr::Feed(m) => m.equals(&feed.title) || m.equals(&feed.url),
+            Expr::Title(m) => match &item.title {
+                Some(title) => m.contains(title),
+                None => false,
+            },
+            Expr::Description(m) => item
+                .description
+                .iter()
+                .chain(item.content.iter())
+                .any(|text| m.contains(text)),
+            Expr::Author(m) => match &item.author {
+                Some(author) => m.contains(author),
+                None => false,
+            },
+            Expr::PublishedAfter(bound) => match item.published_at {
+                Some(ts) => ts > bound.timestamp(now),
+                None => false,
+            },
+            Expr::PublishedBefore(bound) => match item.published_at {
+                Some(ts) => ts < bound.timestamp(now),
+                None => false,
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (at {})", self.message, self.position + 1)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn error<T>(position: usize, message: &str) -> Result<T, ParseError> {
+    Err(ParseError {
+        position,
+        message: message.to_string(),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Word(String),
+    Quoted(String),
+    Regex(String, String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Term { field: Option<String>, value: Value },
+}
+
+fn is_word_end(c: char) -> bool {
+    c.is_whitespace() || c == '(' || c == ')'
+}
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        match chars[i] {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => {
+                tokens.push((start, Token::Open));
+                i += 1;
+                continue;
+            }
+            ')' => {
+                tokens.push((start, Token::Close));
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        // An optional `field:` prefix
+        let mut field = None;
+        let mut j = i;
+        while j < chars.len() && chars[j].is_alphanumeric() {
+            j += 1;
+        }
+        if j > i && j < chars.len() && chars[j] == ':' {
+            field = Some(chars[i..j].iter().collect::<String>().to_lowercase());
+            i = j + 1;
+        }
+
+        let value = match chars.get(i) {
+            Some('"') => {
+                let mut text = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        Some('"') => break,
+                        Some('\\') if chars.get(i + 1) == Some(&'"') => {
+                            text.push('"');
+                            i += 2;
+                        }
+                        Some(c) => {
+                            text.push(*c);
+                            i += 1;
+                        }
+                        None => return error(start, "Unclosed quote"),
+                    }
+                }
+                i += 1;
+                Value::Quoted(text)
+            }
+            Some('/') => {
+                let mut pattern = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        Some('/') => break,
+                        Some('\\') if chars.get(i + 1) == Some(&'/') => {
+                            pattern.push('/');
+                            i += 2;
+                        }
+                        Some(c) => {
+                            pattern.push(*c);
+                            i += 1;
+                        }
+                        None => return error(start, "Unclosed regex"),
+                    }
+                }
+                i += 1;
+                let mut flags = String::new();
+                while i < chars.len() && chars[i].is_alphabetic() {
+                    flags.push(chars[i]);
+                    i += 1;
+                }
+                Value::Regex(pattern, flags)
+            }
+            _ => {
+                let mut word = String::new();
+                while i < chars.len() && !is_word_end(chars[i]) {
+                    word.push(chars[i]);
+                    i += 1;
+                }
+                if word.is_empty() {
+                    return error(i, "Expected a value");
+                }
+                Value::Word(word)
+            }
+        };
+        if i < chars.len() && !is_word_end(chars[i]) {
+            return error(i, "Unexpected character");
+        }
+        tokens.push((start, Token::Term { field, value }));
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |(pos, _)| *pos)
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Term {
+                field: None,
+                value: Value::Word(word),
+            }) => word.eq_ignore_ascii_case(keyword),
+            _ => false,
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.parse_and()?;
+        while self.peek_keyword("or") {
+            self.pos += 1;
+            expr = expr.or(self.parse_and()?);
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.parse_unary()?;
+        loop {
+            if self.peek_keyword("and") {
+                self.pos += 1;
+            } else if self.peek().is_none() || self.peek() == Some(&Token::Close) || self.peek_keyword("or") {
+                break;
+            }
+            expr = expr.and(self.parse_unary()?);
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        let position = self.position();
+        if self.peek_keyword("not") {
+            self.pos += 1;
+            return Ok(self.parse_unary()?.not());
+        }
+        if self.peek_keyword("and") || self.peek_keyword("or") {
+            return error(position, "Expected a term");
+        }
+        match self.tokens.get(self.pos) {
+            Some((_, Token::Open)) => {
+                self.pos += 1;
+                let expr = self.parse_or()?;
+                match self.peek() {
+                    Some(Token::Close) => {
+                        self.pos += 1;
+                        Ok(expr)
+                    }
+                    _ => error(self.position(), "Expected `)`"),
+                }
+            }
+            Some((_, Token::Close)) => error(position, "Unexpected `)`"),
+            Some((_, Token::Term { field, value })) => {
+                let (field, value) = (field.clone(), value.clone());
+                self.pos += 1;
+                parse_term(position, field, value)
+            }
+            None => error(position, "Expected a term"),
+        }
+    }
+}
+
+fn parse_matcher(position: usize, value: Value) -> Result<Matcher, ParseError> {
+    match value {
+        Value::Word(text) | Value::Quoted(text) => Ok(Matcher::Text(text.to_lowercase())),
+        Value::Regex(pattern, flags) => {
+            if let Some(flag) = flags.chars().find(|c| *c != 'i') {
+                return error(position, &format!("Unknown regex flag `{}`", flag));
+            }
+            match RegexBuilder::new(&pattern)
+                .case_insensitive(flags.contains('i'))
+                .build()
+            {
+                Ok(re) => Ok(Matcher::Regex(re)),
+                Err(_) => error(position, "Invalid regex"),
+            }
+        }
+    }
+}
+
+// Parse e.g. `<7d` or `>2021-01-01`
+fn parse_published(position: usize, value: Value) -> Result<Expr, ParseError> {
+    let text = match value {
+        Value::Word(text) | Value::Quoted(text) => text,
+        Value::Regex(..) => return error(position, "Expected e.g. `<7d` or `>2021-01-01`"),
+    };
+    let (less, rest) = if let Some(rest) = text.strip_prefix('<') {
+        (true, rest)
+    } else if let Some(rest) = text.strip_prefix('>') {
+        (false, rest)
+    } else {
+        return error(position, "Expected `<` or `>`");
+    };
+
+    if let Ok(date) = NaiveDate::parse_from_str(rest, "%Y-%m-%d") {
+        let bound = Bound::Time(date.and_hms(0, 0, 0).timestamp());
+        return Ok(if less {
+            Expr::PublishedBefore(bound)
+        } else {
+            Expr::PublishedAfter(bound)
+        });
+    }
+
+    let age = match parse_duration(rest) {
+        Ok(age) => age,
+        Err(err) => return error(position, &format!("Expected a duration or date (e.g. 2021-01-01): {}", err)),
+    };
+
+    // Less than some age is after that point in time
+    let bound = Bound::Age(age);
+    Ok(if less {
+        Expr::PublishedAfter(bound)
+    } else {
+        Expr::PublishedBefore(bound)
+    })
+}
+
+fn parse_term(position: usize, field: Option<String>, value: Value) -> Result<Expr, ParseError> {
+    match field.as_deref() {
+        None => match &value {
+            Value::Word(word) => match word.to_lowercase().as_str() {
+                "read" => Ok(Expr::Read),
+                "unread" => Ok(Expr::Read.not()),
+                "starred" => Ok(Expr::Starred),
+                "unstarred" => Ok(Expr::Starred.not()),
+                _ => Ok(Expr::Title(parse_matcher(position, value)?)),
+            },
+            _ => Ok(Expr::Title(parse_matcher(position, value)?)),
+        },
+        Some("tag") => Ok(Expr::Tag(parse_matcher(position, value)?)),
+        Some("feed") => Ok(Expr::Feed(parse_matcher(position, value)?)),
+        Some("title") => Ok(Expr::Title(parse_matcher(position, value)?)),
+        Some("desc") => Ok(Expr::Description(parse_matcher(position, value)?)),
+        Some("author") => Ok(Expr::Author(parse_matcher(position, value)?)),
+        Some("published") => parse_published(position, value),
+        Some(field) => error(position, &format!("Unknown field `{}`", field)),
+    }
+}
+
+pub fn parse(input: &str) -> Result<Expr, ParseError> {
+    let tokens = tokenize(input)?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        end: input.chars().count(),
+    };
+    let expr = parser.parse_or()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(_) => error(parser.position(), "Unexpected `)`"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed::FeedOptions;
+
+    const NOW: i64 = 1_600_000_000;
+
+    fn item() -> Item {
+        Item {
+            id: None,
+            read: false,
+            starred: true,
+            feed: "https://blog.rust-lang.org/feed.xml".to_string(),
+            guid: "1".to_string(),
+            retrieved_at: NOW,
+            title: Some("Announcing Rust 1.50".to_string()),
+            url: None,
+            author: Some("The Rust Release Team".to_string()),
+            published_at: Some(NOW - 60 * 60 * 24 * 3),
+            description: Some("Stable const generics".to_string()),
+            content: None,
+            article: None,
+            enclosures: Vec::new(),
+        }
+    }
+
+    fn feed() -> Feed {
+        Feed {
+            url: "https://blog.rust-lang.org/feed.xml".to_string(),
+            title: "Rust Blog".to_string(),
+            tags: vec!["tech".to_string(), "Rust".to_string()],
+            options: FeedOptions::default(),
+        }
+    }
+
+    fn matches(query: &str) -> bool {
+        parse(query).unwrap().eval(&item(), &feed(), NOW)
+    }
+
+    fn parse_error(query: &str) -> (usize, String) {
+        let err = parse(query).unwrap_err();
+        (err.position, err.message)
+    }
+
+    #[test]
+    fn flags() {
+        assert!(matches("unread"));
+        assert!(!matches("read"));
+        assert!(matches("starred"));
+        assert!(!matches("unstarred"));
+        assert!(matches("UNREAD"));
+    }
+
+    #[test]
+    fn fields() {
+        assert!(matches("tag:rust"));
+        assert!(!matches("tag:ru"));
+        assert!(matches(r#"feed:"rust blog""#));
+        assert!(matches("feed:https://blog.rust-lang.org/feed.xml"));
+        assert!(matches("title:announcing"));
+        assert!(matches("announcing"));
+        assert!(matches("desc:const"));
+        assert!(matches("author:release"));
+        assert!(!matches("author:nobody"));
+        assert!(matches("Title:1.50"));
+    }
+
+    #[test]
+    fn regexes() {
+        assert!(matches("title:/Rust \\d/"));
+        assert!(!matches("title:/RUST/"));
+        assert!(matches("title:/RUST/i"));
+        assert!(matches("/^announcing/i"));
+        assert!(matches("feed:/^https:/"));
+    }
+
+    #[test]
+    fn published() {
+        assert!(matches("published:<7d"));
+        assert!(!matches("published:<2d"));
+        assert!(matches("published:>2d"));
+        assert!(matches("published:<1w"));
+        assert!(matches("published:>4000m"));
+        assert!(!matches("published:<71h"));
+        assert!(matches("published:>2020-01-01"));
+        assert!(!matches("published:>2020-10-01"));
+        assert!(matches("published:<2021-01-01"));
+    }
+
+    #[test]
+    fn combinators() {
+        assert!(matches("tag:tech and unread"));
+        assert!(matches("tag:tech unread"));
+        assert!(!matches("tag:tech and read"));
+        assert!(matches("read or starred"));
+        assert!(matches("not read"));
+        assert!(matches("not (read or unstarred)"));
+        assert!(!matches("not unread or read"));
+        assert!(matches(
+            r#"tag:tech and not feed:"Hacker News" and unread and published:<7d and title:/rust/i"#
+        ));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse_error("").1, "Expected a term");
+        assert_eq!(parse_error("and"), (0, "Expected a term".to_string()));
+        assert_eq!(parse_error("unread or"), (9, "Expected a term".to_string()));
+        assert_eq!(parse_error("(unread"), (7, "Expected `)`".to_string()));
+        assert_eq!(parse_error("unread)"), (6, "Unexpected `)`".to_string()));
+        assert_eq!(parse_error("tag:"), (4, "Expected a value".to_string()));
+        assert_eq!(parse_error(r#"title:"rust"#), (0, "Unclosed quote".to_string()));
+        assert_eq!(parse_error("title:/rust"), (0, "Unclosed regex".to_string()));
+        assert_eq!(parse_error("title:/(/").1, "Invalid regex");
+        assert_eq!(parse_error("title:/rust/x").1, "Unknown regex flag `x`");
+        assert_eq!(parse_error("foo:bar").1, "Unknown field `foo`");
+        assert_eq!(parse_error(r#"title:"a"b"#), (9, "Unexpected character".to_string()));
+        assert_eq!(parse_error("published:7d").1, "Expected `<` or `>`");
+        assert!(parse_error("published:<7x").1.starts_with("Expected a duration or date"));
+        assert!(parse_error("published:<99999999999999999w").1.contains("too long"));
+        assert_eq!(parse_error("published:/x/").1, "Expected e.g. `<7d` or `>2021-01-01`");
+    }
+
+    #[test]
+    fn error_display() {
+        assert_eq!(parse("unread)").unwrap_err().to_string(), "Unexpected `)` (at 7)");
+    }
+}
@@ -0,0 +1,328 @@
+use html2md::parse_html;
+use html5ever::serialize::{serialize, SerializeOpts, TraversalScope};
+use html5ever::tendril::TendrilSink;
+use html5ever::parse_document;
+use markup5ever_rcdom::{Handle, NodeData, RcDom, SerializableHandle};
+use regex::Regex;
+use reqwest::Url;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/*
+ * A simplified take on Readability's main content extraction:
+ * after dropping elements that are unlikely to be part of the article,
+ * paragraphs score their parent and grandparent by how much text they have,
+ * and the best scoring element (along with any good siblings) is the article.
+ */
+
+// Never part of the article
+const REMOVED_TAGS: &[&str] = &[
+    "script", "style", "noscript", "iframe", "object", "embed", "form", "button",
+    "input", "select", "textarea", "nav", "header", "footer", "aside", "svg", "canvas",
+];
+
+const UNLIKELY: &str = r"(?i)banner|breadcrumb|combx|comment|community|cookie|disqus|extra|foot|header|legends|menu|modal|related|remark|replies|rss|shoutbox|sidebar|skyscraper|social|share|sponsor|ad-break|agegate|pagination|pager|popup|promo|subscribe|newsletter";
+const LIKELY: &str = r"(?i)and|article|body|column|content|main|shadow|story|entry|post|text|blog";
+const NEGATIVE: &str = r"(?i)hidden|banner|combx|comment|com-|contact|foot|footer|footnote|masthead|media|meta|outbrain|promo|related|scroll|share|shoutbox|sidebar|skyscraper|sponsor|shopping|tags|tool|widget";
+const POSITIVE: &str = r"(?i)article|body|content|entry|hentry|h-entry|main|page|pagination|post|text|blog|story";
+
+// Paragraphs shorter than this don't count towards a score
+const MIN_PARAGRAPH_LEN: usize = 25;
+
+struct Patterns {
+    unlikely: Regex,
+    likely: Regex,
+    negative: Regex,
+    positive: Regex,
+}
+
+struct Candidate {
+    node: Handle,
+    parent: Option<Handle>,
+    score: f64,
+}
+
+/// Extract the main content of an HTML page as markdown.
+/// Relative links and images are resolved against `base`.
+pub fn extract(html: &[u8], base: &Url) -> Option<String> {
+    let dom = parse_document(RcDom::default(), Default::default())
+        .from_utf8()
+        .read_from(&mut &html[..])
+        .ok()?;
+    let patterns = Patterns {
+        unlikely: Regex::new(UNLIKELY).unwrap(),
+        likely: Regex::new(LIKELY).unwrap(),
+        negative: Regex::new(NEGATIVE).unwrap(),
+        positive: Regex::new(POSITIVE).unwrap(),
+    };
+    clean(&dom.document, base, &patterns);
+
+    let mut candidates: HashMap<*const markup5ever_rcdom::Node, Candidate> = HashMap::new();
+    score_paragraphs(&dom.document, &mut Vec::new(), &mut candidates, &patterns);
+
+    // Links are mostly navigation, so penalize link-heavy candidates
+    let top = candidates
+        .values()
+        .map(|candidate| (candidate, candidate.score * (1. - link_density(&candidate.node))))
+        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())?;
+    let (top, top_score) = top;
+
+    let mut html = Vec::new();
+    let threshold = f64::max(10., top_score * 0.2);
+    let siblings = match &top.parent {
+        Some(parent) => parent.children.borrow().clone(),
+        None => vec![top.node.clone()],
+    };
+    for sibling in siblings {
+        let include = if Rc::ptr_eq(&sibling, &top.node) {
+            true
+        } else if let Some(candidate) = candidates.get(&Rc::as_ptr(&sibling)) {
+            candidate.score * (1. - link_density(&sibling)) >= threshold
+        } else {
+            // Paragraphs that just didn't share a parent with the others
+            tag_name(&sibling) == Some("p")
+                && text_len(&sibling) > 80
+                && link_density(&sibling) < 0.25
+        };
+        if include {
+            let opts = SerializeOpts {
+                traversal_scope: TraversalScope::IncludeNode,
+                ..Default::default()
+            };
+            serialize(&mut html, &SerializableHandle::from(sibling), opts).ok()?;
+        }
+    }
+
+    let markdown = parse_html(&String::from_utf8_lossy(&html));
+    if markdown.trim().is_empty() {
+        None
+    } else {
+        Some(markdown)
+    }
+}
+
+fn tag_name(node: &Handle) -> Option<&str> {
+    match &node.data {
+        NodeData::Element { name, .. } => Some(&name.local),
+        _ => None,
+    }
+}
+
+fn attr(node: &Handle, attr_name: &str) -> Option<String> {
+    match &node.data {
+        NodeData::Element { attrs, .. } => attrs
+            .borrow()
+            .iter()
+            .find(|attr| &*attr.name.local == attr_name)
+            .map(|attr| attr.value.to_string()),
+        _ => None,
+    }
+}
+
+// The class and id, for guessing what an element is for
+fn class_and_id(node: &Handle) -> String {
+    format!(
+        "{} {}",
+        attr(node, "class").unwrap_or_default(),
+        attr(node, "id").unwrap_or_default()
+    )
+}
+
+// Drop comments and unlikely elements, and make links absolute
+fn clean(node: &Handle, base: &Url, patterns: &Patterns) {
+    node.children.borrow_mut().retain(|child| match &child.data {
+        NodeData::Comment { .. } => false,
+        NodeData::Element { name, .. } => {
+            let tag: &str = &name.local;
+            if REMOVED_TAGS.contains(&tag) {
+                return false;
+            }
+            if tag == "body" || tag == "article" {
+                return true;
+            }
+            let class_and_id = class_and_id(child);
+            !patterns.unlikely.is_match(&class_and_id) || patterns.likely.is_match(&class_and_id)
+        }
+        _ => true,
+    });
+
+    if let NodeData::Element { attrs, .. } = &node.data {
+        for attr in attrs.borrow_mut().iter_mut() {
+            let name: &str = &attr.name.local;
+            if name == "href" || name == "src" {
+                if let Ok(url) = base.join(&attr.value) {
+                    attr.value = url.as_str().into();
+                }
+            }
+        }
+    }
+
+    for child in node.children.borrow().iter() {
+        clean(child, base, patterns);
+    }
+}
+
+fn score_paragraphs(
+    node: &Handle,
+    ancestors: &mut Vec<Handle>,
+    candidates: &mut HashMap<*const markup5ever_rcdom::Node, Candidate>,
+    patterns: &Patterns,
+) {
+    if let Some("p") | Some("pre") | Some("td") = tag_name(node) {
+        let text = text(node);
+        let len = text.trim().chars().count();
+        if len >= MIN_PARAGRAPH_LEN {
+            // More text and more commas is more likely prose
+            let commas = text.matches(',').count();
+            let score = 1. + commas as f64 + f64::min(len as f64 / 100., 3.);
+
+            // The parent gets the full score, the grandparent half
+            let n = ancestors.len();
+            for (depth, divider) in [(1, 1.), (2, 2.)].iter() {
+                if n < *depth {
+                    break;
+                }
+                let ancestor = &ancestors[n - depth];
+                let candidate = candidates.entry(Rc::as_ptr(ancestor)).or_insert_with(|| Candidate {
+                    node: ancestor.clone(),
+                    parent: if n > *depth { Some(ancestors[n - depth - 1].clone()) } else { None },
+                    score: initial_score(ancestor, patterns),
+                });
+                candidate.score += score / divider;
+            }
+        }
+    }
+
+    ancestors.push(node.clone());
+    for child in node.children.borrow().iter() {
+        score_paragraphs(child, ancestors, candidates, patterns);
+    }
+    ancestors.pop();
+}
+
+fn initial_score(node: &Handle, patterns: &Patterns) -> f64 {
+    let tag_score = match tag_name(node) {
+        Some("article") => 10.,
+        Some("div") => 5.,
+        Some("pre") | Some("td") | Some("blockquote") => 3.,
+        Some("address") | Some("ol") | Some("ul") | Some("dl") | Some("dd") | Some("dt") | Some("li") | Some("form") => -3.,
+        Some("h1") | Some("h2") | Some("h3") | Some("h4") | Some("h5") | Some("h6") | Some("th") => -5.,
+        _ => 0.,
+    };
+    let class_and_id = class_and_id(node);
+    let mut class_score = 0.;
+    if patterns.negative.is_match(&class_and_id) {
+        class_score -= 25.;
+    }
+    if patterns.positive.is_match(&class_and_id) {
+        class_score += 25.;
+    }
+    tag_score + class_score
+}
+
+fn text(node: &Handle) -> String {
+    let mut text = String::new();
+    collect_text(node, &mut text);
+    text
+}
+
+fn collect_text(node: &Handle, text: &mut String) {
+    if let NodeData::Text { contents } = &node.data {
+        text.push_str(&contents.borrow());
+    }
+    for child in node.children.borrow().iter() {
+        collect_text(child, text);
+    }
+}
+
+fn text_len(node: &Handle) -> usize {
+    text(node).trim().chars().count()
+}
+
+// How much of the text is in links
+fn link_density(node: &Handle) -> f64 {
+    let len = text_len(node);
+    if len == 0 {
+        return 0.;
+    }
+    let mut link_len = 0;
+    let mut stack = vec![node.clone()];
+    while let Some(node) = stack.pop() {
+        if tag_name(&node) == Some("a") {
+            link_len += text_len(&node);
+        } else {
+            stack.extend(node.children.borrow().iter().cloned());
+        }
+    }
+    link_len as f64 / len as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extract_from(html: &str) -> Option<String> {
+        let base = Url::parse("https://example.com/news/story.html").unwrap();
+        extract(html.as_bytes(), &base)
+    }
+
+    const ARTICLE: &str = r##"<html><head><title>Story</title><script>var tracking = 1;</script></head><body>
+        <nav class="menu"><a href="/">Home</a> <a href="/about">About</a></nav>
+        <div id="sidebar"><p>Subscribe to our newsletter, it is great, really, truly great and long enough.</p></div>
+        <div class="article-body">
+          <h1>Headline</h1>
+          <p>The first paragraph of the story, which is long enough to count, and has commas, lots of them.</p>
+          <p>A second paragraph with a <a href="/link">relative link</a> and more text so that it scores well too.</p>
+          <img src="img/pic.png">
+          <!-- a comment -->
+          <div class="share-buttons"><a href="#">Tweet</a></div>
+        </div>
+        <footer><p>Copyright 2021, all rights reserved, this is a long footer paragraph text.</p></footer>
+        </body></html>"##;
+
+    #[test]
+    fn keeps_the_article() {
+        let markdown = extract_from(ARTICLE).unwrap();
+        assert!(markdown.contains("Headline"));
+        assert!(markdown.contains("The first paragraph of the story"));
+        assert!(markdown.contains("A second paragraph"));
+    }
+
+    #[test]
+    fn drops_everything_else() {
+        let markdown = extract_from(ARTICLE).unwrap();
+        for dropped in &["tracking", "Home", "newsletter", "Tweet", "Copyright", "a comment"] {
+            assert!(!markdown.contains(dropped), "{}", dropped);
+        }
+    }
+
+    #[test]
+    fn resolves_relative_urls() {
+        let markdown = extract_from(ARTICLE).unwrap();
+        assert!(markdown.contains("https://example.com/link"));
+        assert!(markdown.contains("https://example.com/news/img/pic.png"));
+    }
+
+    #[test]
+    fn prefers_prose_over_links() {
+        let html = r#"<body>
+            <div class="list">
+              <p><a href="/1">A long list of links to other stories, one after another</a></p>
+              <p><a href="/2">Another long link to yet another story, and then some more</a></p>
+            </div>
+            <div>
+              <p>Some actual prose, with commas, clauses, and enough words to be an article.</p>
+            </div>
+            </body>"#;
+        let markdown = extract_from(html).unwrap();
+        assert!(markdown.contains("actual prose"));
+        assert!(!markdown.contains("another story"));
+    }
+
+    #[test]
+    fn nothing_to_extract() {
+        assert_eq!(extract_from(""), None);
+        assert_eq!(extract_from("<html><body><p>Too short.</p></body></html>"), None);
+        assert_eq!(extract_from("<html><body><nav><p>Only navigation, which is never part of the article.</p></nav></body></html>"), None);
+    }
+}
@@ -0,0 +1,220 @@
+use super::db::Database;
+use super::feed::{get_items, Feed, Fetch};
+use chrono::Utc;
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+
+/// The outcome of updating one feed:
+/// how many new items it had, or why it failed
+pub struct FeedUpdate {
+    pub feed: String,
+    pub title: String,
+    pub result: Result<usize, String>,
+}
+
+/// How far along an update cycle is
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Progress {
+    pub total: usize,
+    pub completed: usize,
+    pub failed: usize,
+    // Urls and titles of the feeds still being fetched, by url
+    // as titles aren't unique
+    pub in_flight: Vec<(String, String)>,
+    // Titles of the feeds that had new items, with how many
+    pub new_items: Vec<(String, usize)>,
+}
+
+impl Progress {
+    pub fn new(feeds: &[Feed]) -> Progress {
+        Progress {
+            total: feeds.len(),
+            in_flight: feeds.iter().map(|feed| (feed.url.clone(), feed.title.clone())).collect(),
+            ..Progress::default()
+        }
+    }
+
+    pub fn record(&mut self, update: &FeedUpdate) {
+        self.completed += 1;
+        if let Some(i) = self.in_flight.iter().position(|(url, _)| *url == update.feed) {
+            self.in_flight.remove(i);
+        }
+        match update.result {
+            Ok(0) => {}
+            Ok(count) => self.new_items.push((update.title.clone(), count)),
+            Err(_) => self.failed += 1,
+        }
+    }
+
+    pub fn new_item_count(&self) -> usize {
+        self.new_items.iter().map(|(_, count)| count).sum()
+    }
+}
+
+/// Keeps track of when each feed is next due for an update,
+/// by its own update interval or the default one
+pub struct Schedule {
+    default_interval: Duration,
+    next_update: HashMap<String, Instant>,
+}
+
+impl Schedule {
+    pub fn new(default_interval: Duration) -> Schedule {
+        Schedule {
+            default_interval,
+            next_update: HashMap::new(),
+        }
+    }
+
+    /// The enabled feeds that are due, or were asked to be refreshed
+    /// (`Some(None)` for all of them), which are then scheduled again
+    pub fn take_due(&mut self, feeds: Vec<Feed>, refresh: Option<Option<String>>) -> Vec<Feed> {
+        let now = Instant::now();
+        let feeds: Vec<Feed> = feeds.into_iter().filter(|feed| !feed.options.disabled).collect();
+
+        // Forget feeds that were removed or disabled
+        self.next_update
+            .retain(|url, _| feeds.iter().any(|feed| feed.url == *url));
+
+        let due: Vec<Feed> = feeds
+            .into_iter()
+            .filter(|feed| {
+                let refreshed = match &refresh {
+                    Some(Some(url)) => feed.url.trim() == url.trim(),
+                    Some(None) => true,
+                    None => false,
+                };
+                refreshed
+                    || match self.next_update.get(&feed.url) {
+                        Some(next) => *next <= now,
+                        None => true,
+                    }
+            })
+            .collect();
+        for feed in &due {
+            let interval = match feed.options.update_interval {
+                Some(secs) => Duration::from_secs(secs as u64),
+                None => self.default_interval,
+            };
+            self.next_update.insert(feed.url.clone(), now + interval);
+        }
+        due
+    }
+
+    /// How long until the next feed is due
+    pub fn wait(&self) -> Duration {
+        let now = Instant::now();
+        match self.next_update.values().min() {
+            Some(next) => next.saturating_duration_since(now),
+            None => self.default_interval,
+        }
+    }
+}
+
+/// Fetch feeds concurrently and save their new items, recording
+/// each feed's health and reporting it to `on_update` as it finishes.
+/// Stops early if `on_update` returns false.
+pub fn update_feeds<F>(runtime: &mut Runtime, db: &Database, feeds: Vec<Feed>, mut on_update: F)
+where
+    F: FnMut(FeedUpdate) -> bool,
+{
+    let mut futs: FuturesUnordered<_> = feeds
+        .into_iter()
+        .map(|feed| {
+            let cache = db.get_http_cache(&feed.url).unwrap_or_default();
+            async move {
+                let result = get_items(feed.url.clone(), &feed.options, cache).await;
+                (feed.url, feed.title, feed.options, result)
+            }
+        })
+        .collect();
+    // Checked once, as it's rare and only ever goes from true to false
+    let legacy = db.has_legacy_items().unwrap_or(false);
+    runtime.block_on(async {
+        while let Some((feed_url, feed_title, options, result)) = futs.next().await {
+            let result = match result {
+                Ok(fetch) => {
+                    let mut new_items = 0;
+                    let status = match fetch {
+                        Fetch::NotModified => 304,
+                        Fetch::Updated { status, items, cache } => {
+                            for item in items {
+                                if legacy {
+                                    db.adopt_legacy_item(&item).unwrap();
+                                }
+                                if db.add_item(&item, &feed_title).unwrap() {
+                                    new_items += 1;
+                                }
+                            }
+                            db.set_http_cache(&feed_url, &cache).unwrap();
+                            if options.max_age.is_some() || options.max_items.is_some() {
+                                let before = options.max_age.map(|age| Utc::now().timestamp() - age);
+                                db.prune_items(&feed_url, before, options.max_items).unwrap();
+                            }
+                            status
+                        }
+                    };
+                    db.record_fetch_success(&feed_url, status, Utc::now().timestamp())
+                        .unwrap();
+                    Ok(new_items)
+                }
+                Err(err) => {
+                    let status = err
+                        .downcast_ref::<reqwest::Error>()
+                        .and_then(|err| err.status())
+                        .map(|status| status.as_u16());
+                    db.record_fetch_error(&feed_url, status, &err.to_string())
+                        .unwrap();
+                    Err(err.to_string())
+                }
+            };
+            let update = FeedUpdate {
+                feed: feed_url,
+                title: feed_title,
+                result,
+            };
+            if !on_update(update) {
+                break;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed::FeedOptions;
+
+    fn feed(url: &str, title: &str) -> Feed {
+        Feed {
+            url: url.to_string(),
+            title: title.to_string(),
+            tags: Vec::new(),
+            options: FeedOptions::default(),
+        }
+    }
+
+    #[test]
+    fn progress_with_duplicate_titles() {
+        let feeds = vec![feed("https://a.com", "Blog"), feed("https://b.com", "Blog"), feed("https://c.com", "C")];
+        let mut progress = Progress::new(&feeds);
+        progress.record(&FeedUpdate {
+            feed: "https://b.com".to_string(),
+            title: "Blog".to_string(),
+            result: Ok(2),
+        });
+        progress.record(&FeedUpdate {
+            feed: "https://c.com".to_string(),
+            title: "C".to_string(),
+            result: Err("HTTP 404".to_string()),
+        });
+        assert_eq!(progress.in_flight, vec![("https://a.com".to_string(), "Blog".to_string())]);
+        assert_eq!(progress.completed, 2);
+        assert_eq!(progress.failed, 1);
+        assert_eq!(progress.new_item_count(), 2);
+    }
+}
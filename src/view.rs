@@ -1,4 +1,9 @@
 use super::app::{App, InputMode, Status};
+use super::db::{DownloadState, Enclosure};
+use super::keys::Action;
+use super::links::extract_links;
+use super::update::Progress;
+use std::cmp::Reverse;
 use regex::Regex;
 use chrono::{TimeZone, Local};
 use tui::{
@@ -6,8 +11,8 @@ use tui::{
     backend::Backend,
     style::{Color, Modifier, Style},
     text::{Span, Spans, Text},
-    layout::{Constraint, Direction, Layout, Alignment},
-    widgets::{Block, Borders, Cell, Row, Table, Paragraph, Wrap},
+    layout::{Constraint, Direction, Layout, Alignment, Rect},
+    widgets::{Block, Borders, Cell, Clear, List, ListItem, Row, Table, Paragraph, Wrap},
 };
 
 // Split a string on a regex, keeping the matching parts
@@ -31,17 +36,123 @@ pub fn split_keep<'a>(r: &Regex, text: &'a str) -> Vec<(&'a str, bool)> {
 }
 
 
+// e.g. "Updating 12/48 (2 failed) Hacker News "
+fn update_progress(progress: &Progress) -> String {
+    let mut msg = format!("Updating {}/{}", progress.completed, progress.total);
+    if progress.failed > 0 {
+        msg.push_str(&format!(" ({} failed)", progress.failed));
+    }
+    if let Some((_, title)) = progress.in_flight.first() {
+        msg.push(' ');
+        msg.push_str(title);
+    }
+    msg.push(' ');
+    msg
+}
+
+// e.g. "[Enter] apply [Esc] cancel ", skipping unbound actions
+fn key_hints(app: &App, hints: &[(Action, &str)]) -> String {
+    let mut msg = String::new();
+    for (action, label) in hints {
+        if let Some(keys) = app.keymap.hint(*action) {
+            msg.push_str(&format!("{} {} ", keys, label));
+        }
+    }
+    msg
+}
+
+// e.g. "1.5 MB"
+fn format_size(bytes: i64) -> String {
+    let units = ["KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = "B";
+    for u in units.iter() {
+        if size < 1000. {
+            break;
+        }
+        size /= 1000.;
+        unit = u;
+    }
+    if unit == "B" {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, unit)
+    }
+}
+
+// e.g. "https://example.com/ep1.mp3 (audio/mpeg, 52.3 MB, 1:02:03) downloading 45%"
+fn enclosure_line(enclosure: &Enclosure) -> String {
+    let mut details = Vec::new();
+    if let Some(mime_type) = &enclosure.mime_type {
+        details.push(mime_type.clone());
+    }
+    if let Some(length) = enclosure.length {
+        details.push(format_size(length));
+    }
+    if let Some(secs) = enclosure.duration {
+        details.push(if secs >= 3600 {
+            format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+        } else {
+            format!("{}:{:02}", secs / 60, secs % 60)
+        });
+    }
+    let mut line = enclosure.url.clone();
+    if !details.is_empty() {
+        line.push_str(&format!(" ({})", details.join(", ")));
+    }
+    if let Some(download) = &enclosure.download {
+        let state = match download.state {
+            DownloadState::Queued => "queued".to_string(),
+            DownloadState::Downloading => match download.total {
+                Some(total) if total > 0 => format!("downloading {}%", download.downloaded * 100 / total),
+                _ => format!("downloading {}", format_size(download.downloaded)),
+            },
+            DownloadState::Done => format!("saved to {}", download.path.display()),
+            DownloadState::Failed => format!("failed: {}", download.error.as_deref().unwrap_or("unknown error")),
+        };
+        line.push(' ');
+        line.push_str(&state);
+    }
+    line
+}
+
+// e.g. "15 new: Hacker News 12, Lobsters 3 "
+fn update_summary(progress: &Progress) -> String {
+    let mut new_items = progress.new_items.clone();
+    new_items.sort_by_key(|(_, count)| Reverse(*count));
+    let mut msg = format!("{} new", progress.new_item_count());
+    for (i, (title, count)) in new_items.iter().enumerate() {
+        // Only the busiest feeds, the status bar is short
+        if i == 3 {
+            msg.push_str(", ...");
+            break;
+        }
+        msg.push_str(if i == 0 { ": " } else { ", " });
+        msg.push_str(&format!("{} {}", title, count));
+    }
+    if progress.failed > 0 {
+        msg.push_str(&format!(" ({} failed)", progress.failed));
+    }
+    msg.push(' ');
+    msg
+}
+
 pub fn render_browser<B>(app: &mut App, frame: &mut Frame<B>) where B: Backend {
     // Status bar
-    let update_str = match app.status {
-        Status::Updating => "Updating...",
-        _ => ""
+    let update_str = match &app.status {
+        Status::Updating(progress) => update_progress(progress),
+        Status::Updated(progress) => update_summary(progress),
+        Status::Idle => String::new(),
     };
     let (msg, style) = match app.input_mode {
         InputMode::Normal => (
             vec![
                 Span::raw(update_str),
-                Span::raw(format!("[{}{}{}{}]", match app.filter.read {
+                Span::raw(match &app.message {
+                    Some(message) => format!("{} ", message),
+                    None => String::new(),
+                }),
+                Span::raw(format!("[{}{}{}{}{}]", match app.filter.read {
                     Some(b) => if b {
                         "R"
                     } else {
@@ -63,8 +174,29 @@ pub fn render_browser<B>(app: &mut App, frame: &mut Frame<B>) where B: Backend {
                     ""
                 } else {
                     "C"
+                }, if app.filter.keywords.is_empty() {
+                    ""
+                } else if app.filter.use_keywords {
+                    "K"
+                } else {
+                    "k"
                 })),
                 Span::raw(format!("[{} unread] ", app.items.iter().filter(|i| !i.read).fold(0, |c, _| c + 1))),
+                Span::raw(match &app.view_name {
+                    Some(name) => format!("<{}> ", name),
+                    None => String::new(),
+                }),
+                Span::raw(if app.filter.query_raw.is_empty() {
+                    String::new()
+                } else {
+                    format!("[{}] ", app.filter.query_raw)
+                }),
+                Span::raw(match &app.fts_query {
+                    Some(query) => format!("[search: {} ({} results)] ", query, app.items.len()),
+                    None => String::new(),
+                }),
+                // The start of a key sequence
+                Span::styled(app.keymap.pending(), Style::default().add_modifier(Modifier::BOLD)),
             ],
             Style::default(),
         ),
@@ -75,6 +207,71 @@ pub fn render_browser<B>(app: &mut App, frame: &mut Frame<B>) where B: Backend {
             ],
             Style::default(),
         ),
+        InputMode::Query => {
+            let mut spans = vec![
+                Span::raw("filter: "),
+                Span::styled(&app.query_input, Style::default().add_modifier(Modifier::BOLD)),
+            ];
+            if let Some(err) = &app.query_error {
+                spans.push(Span::styled(format!("  {}", err), Style::default().fg(Color::Red)));
+            }
+            (spans, Style::default())
+        },
+        InputMode::FullTextSearch => (
+            vec![
+                Span::raw("search all: "),
+                Span::styled(&app.search_input_raw, Style::default().add_modifier(Modifier::BOLD)),
+            ],
+            Style::default(),
+        ),
+        InputMode::Health => (vec![], Style::default()),
+        InputMode::Help => (
+            vec![Span::raw(key_hints(app, &[
+                (Action::PopupNext, "down"),
+                (Action::PopupPrevious, "up"),
+                (Action::PopupClose, "close"),
+            ]))],
+            Style::default(),
+        ),
+        InputMode::Tags => (
+            vec![Span::raw(key_hints(app, &[
+                (Action::PopupToggle, "mark"),
+                (Action::PopupSelect, "apply"),
+                (Action::PopupClose, "cancel"),
+            ]))],
+            Style::default(),
+        ),
+        InputMode::Views => (
+            vec![Span::raw(key_hints(app, &[
+                (Action::PopupSelect, "switch view"),
+                (Action::PopupClose, "cancel"),
+            ]))],
+            Style::default(),
+        ),
+        InputMode::Links => (
+            vec![
+                Span::raw("[0-9] pick "),
+                Span::raw(key_hints(app, &[
+                    (Action::PopupSelect, "open"),
+                    (Action::CopyLink, "copy"),
+                    (Action::PopupClose, "cancel"),
+                ])),
+                Span::styled(&app.selector.query, Style::default().add_modifier(Modifier::BOLD)),
+            ],
+            Style::default(),
+        ),
+        InputMode::Channels => (
+            vec![
+                Span::raw(key_hints(app, &[
+                    (Action::PopupToggle, "mark"),
+                    (Action::PopupSelect, "apply"),
+                    (Action::PopupClose, "cancel"),
+                ])),
+                Span::raw("filter: "),
+                Span::styled(&app.selector.query, Style::default().add_modifier(Modifier::BOLD)),
+            ],
+            Style::default(),
+        ),
     };
     let mut text = Text::from(Spans::from(msg));
     text.patch_style(style);
@@ -92,14 +289,70 @@ pub fn render_browser<B>(app: &mut App, frame: &mut Frame<B>) where B: Backend {
             let mut text = vec![
                 Spans::from(
                     Span::styled(item.title.as_deref().unwrap_or("<no title>"), Style::default().fg(Color::Yellow))),
-                Spans::from(format!("{} ({})", app.feeds[&item.feed].title.clone(), item.feed.clone())),
+                // Search results can be from feeds that have since been removed
+                Spans::from(match app.feeds.get(&item.feed) {
+                    Some(feed) => format!("{} ({})", feed.title, item.feed),
+                    None => item.feed.clone(),
+                }),
                 Spans::from(item.url.as_deref().unwrap_or("<no url>")),
-                Spans::from(pub_date),
+                Spans::from(match &item.author {
+                    Some(author) => format!("{} by {}", pub_date, author),
+                    None => pub_date,
+                }),
                 Spans::from("\n"),
             ];
 
-            for line in item.description.as_deref().unwrap_or("<no description>").split('\n') {
-                text.push(Spans::from(line));
+            if let Some(snippet) = app.fts_snippets.get(i) {
+                text.insert(4, Spans::from(Span::styled(
+                    format!("Match: {}", snippet), Style::default().fg(Color::Cyan))));
+            }
+
+            if !item.enclosures.is_empty() {
+                for enclosure in &item.enclosures {
+                    text.push(Spans::from(Span::styled(
+                        enclosure_line(enclosure), Style::default().fg(Color::Green))));
+                }
+                if let Some(keys) = app.keymap.hint(Action::DownloadAttachments) {
+                    text.push(Spans::from(Span::styled(
+                        format!("{} to download attachments", keys), Style::default().fg(Color::DarkGray))));
+                }
+                text.push(Spans::from("\n"));
+            }
+
+            let summary = item.description.as_deref();
+            let content = item.article.as_deref().or(item.content.as_deref());
+            let toggle = app.keymap.hint(Action::ToggleSummary);
+            let hint = match (summary, content) {
+                (Some(_), Some(_)) => Some(match (app.show_summary, toggle) {
+                    (true, Some(keys)) => format!("Summary, {} for full content", keys),
+                    (true, None) => "Summary".to_string(),
+                    (false, Some(keys)) => format!("Full content, {} for summary", keys),
+                    (false, None) => "Full content".to_string(),
+                }),
+                (_, None) if item.url.is_some() => app.keymap.hint(Action::FetchArticle)
+                    .map(|keys| format!("{} to fetch the full article", keys)),
+                _ => None,
+            };
+            if let Some(hint) = hint {
+                text.push(Spans::from(Span::styled(hint, Style::default().fg(Color::DarkGray))));
+            }
+
+            // Links are listed after the body, to be opened by number
+            let (body, links) = extract_links(app.item_body(item).unwrap_or("<no description>"));
+            for line in body.split('\n') {
+                text.push(Spans::from(line.to_string()));
+            }
+            if !links.is_empty() {
+                text.push(Spans::from("\n"));
+                let hint = match app.keymap.hint(Action::ShowLinks) {
+                    Some(keys) => format!("Links, [0-9] or {} to open:", keys),
+                    None => "Links, [0-9] to open:".to_string(),
+                };
+                text.push(Spans::from(Span::styled(hint, Style::default().fg(Color::DarkGray))));
+                for (i, link) in links.iter().enumerate() {
+                    text.push(Spans::from(Span::styled(
+                        format!("[{}] {}", i + 1, link), Style::default().fg(Color::Blue))));
+                }
             }
 
             Paragraph::new(text.clone())
@@ -143,8 +396,8 @@ pub fn render_browser<B>(app: &mut App, frame: &mut Frame<B>) where B: Backend {
             .height(1);
 
         let regex = match app.input_mode {
-            InputMode::Normal => &app.search_query,
-            InputMode::Search => &app.search_input
+            InputMode::Search => &app.search_input,
+            _ => &app.search_query,
         };
 
         let rows = app.table.items.iter().enumerate().map(|(i, item)| {
@@ -199,4 +452,119 @@ pub fn render_browser<B>(app: &mut App, frame: &mut Frame<B>) where B: Backend {
         frame.render_widget(reader, chunks[1]);
         frame.render_widget(status_bar, chunks[2]);
     }
+
+    match app.input_mode {
+        InputMode::Tags => render_selector(app, "Tags", true, frame),
+        InputMode::Channels => render_selector(app, "Channels", true, frame),
+        InputMode::Views => render_selector(app, "Views", false, frame),
+        InputMode::Links => render_selector(app, "Links", false, frame),
+        InputMode::Help => render_help(app, frame),
+        _ => {}
+    }
+}
+
+// https://github.com/fdehau/tui-rs/blob/master/examples/popup.rs
+fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
+    let popup_layout = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Percentage((100 - percent_y) / 2),
+            Constraint::Percentage(percent_y),
+            Constraint::Percentage((100 - percent_y) / 2),
+        ].as_ref())
+        .split(r);
+
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage((100 - percent_x) / 2),
+            Constraint::Percentage(percent_x),
+            Constraint::Percentage((100 - percent_x) / 2),
+        ].as_ref())
+        .split(popup_layout[1])[1]
+}
+
+fn render_help<B>(app: &mut App, frame: &mut Frame<B>) where B: Backend {
+    let rows = app.help.items.iter().map(|row| {
+        Row::new(vec![
+            Cell::from(row[0].as_str()).style(Style::default().fg(Color::Yellow)),
+            Cell::from(row[1].as_str()).style(Style::default().fg(Color::DarkGray)),
+            Cell::from(row[2].as_str()),
+        ])
+    });
+    let table = Table::new(rows)
+        .block(Block::default().title("Keys").borders(Borders::ALL))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .widths(&[
+            Constraint::Length(14),
+            Constraint::Length(22),
+            Constraint::Min(10),
+        ]);
+
+    let area = centered_rect(80, 80, frame.size());
+    frame.render_widget(Clear, area);
+    frame.render_stateful_widget(table, area, &mut app.help.state);
+}
+
+fn render_selector<B>(app: &mut App, title: &str, checkboxes: bool, frame: &mut Frame<B>) where B: Backend {
+    let selector = &mut app.selector;
+    let options: Vec<ListItem> = selector.matches.iter().map(|i| {
+        let (value, label) = &selector.options[*i];
+        if checkboxes {
+            let mark = if selector.marked.contains(value) { "[x]" } else { "[ ]" };
+            ListItem::new(format!("{} {}", mark, label))
+        } else {
+            ListItem::new(label.clone())
+        }
+    }).collect();
+    let list = List::new(options)
+        .block(Block::default().title(title).borders(Borders::ALL))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+    let area = centered_rect(50, 60, frame.size());
+    frame.render_widget(Clear, area);
+    frame.render_stateful_widget(list, area, &mut selector.state);
+}
+
+
+pub fn render_health<B>(app: &mut App, frame: &mut Frame<B>) where B: Backend {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+             Constraint::Min(1),
+             Constraint::Length(1),
+        ].as_ref())
+        .split(frame.size());
+
+    let header_cells = ["Feed", "Status", "HTTP", "Last success", "Last error"]
+        .iter()
+        .map(|h| Cell::from(*h).style(Style::default().fg(Color::Red)));
+    let header = Row::new(header_cells).height(1);
+
+    let rows = app.health.items.iter().map(|(status, row)| {
+        let style = if status.failures > 0 {
+            Style::default().fg(Color::Red)
+        } else {
+            Style::default()
+        };
+        Row::new(row.iter().map(|c| Cell::from(c.as_str()))).style(style)
+    });
+    let feed_list = Table::new(rows)
+        .header(header)
+        .block(Block::default().borders(Borders::BOTTOM))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .widths(&[
+            Constraint::Percentage(30),
+            Constraint::Length(10),
+            Constraint::Length(4),
+            Constraint::Length(14),
+            Constraint::Percentage(50),
+        ]);
+
+    let failing = app.health.items.iter().filter(|(status, _)| status.failures > 0).count();
+    let status_bar = Paragraph::new(format!("[{} feeds, {} failing]", app.health.items.len(), failing))
+        .style(Style::default().bg(Color::DarkGray));
+
+    frame.render_stateful_widget(feed_list, chunks[0], &mut app.health.state);
+    frame.render_widget(status_bar, chunks[1]);
 }